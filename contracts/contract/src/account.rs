@@ -1,16 +1,87 @@
 use crate::*;
 
-use std::collections::HashMap;
+pub type LockId = u64;
 
 #[near(serializers = [borsh, json])]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 pub struct LockInfo {
     pub locked_balance: U128,
     pub unlock_time_sec: u32,
+    pub schedule: Option<UnlockSchedule>,
+    /// Account allowed to claw back the part of a scheduled lock that has not vested yet.
+    pub revoker_id: Option<AccountId>,
 }
 
 impl LockInfo {
+    pub fn new(amount: U128, unlock_time_sec: u32) -> Self {
+        Self {
+            locked_balance: amount,
+            unlock_time_sec,
+            schedule: None,
+            revoker_id: None,
+        }
+    }
+
+    pub fn new_with_schedule(amount: U128, unlock_time_sec: u32, schedule: UnlockSchedule) -> Self {
+        Self {
+            locked_balance: amount,
+            unlock_time_sec,
+            schedule: Some(schedule),
+            revoker_id: None,
+        }
+    }
+
+    /// Amount the owner may withdraw right now. A scheduled lock releases its deposit
+    /// gradually; everything already withdrawn or burnt counts against the released part.
+    pub fn withdrawable_balance(&self, current_time_sec: u32) -> u128 {
+        if let Some(schedule) = self.schedule.as_ref() {
+            let released = schedule.total_balance() - self.locked_balance.0;
+            schedule
+                .unlocked_balance(self.unlock_time_sec, current_time_sec)
+                .saturating_sub(released)
+        } else if self.unlock_time_sec <= current_time_sec {
+            self.locked_balance.0
+        } else {
+            0
+        }
+    }
+
+    /// Cuts the lock down to what has vested by `current_time_sec` and returns the
+    /// revoked amount. What remains is an ordinary lock that is unlocked right away.
+    pub fn revoke(&mut self, current_time_sec: u32) -> u128 {
+        let schedule = self.schedule.take().expect("Lock not revocable");
+        let unvested = schedule.total_balance()
+            - schedule.unlocked_balance(self.unlock_time_sec, current_time_sec);
+        let revoked = std::cmp::min(unvested, self.locked_balance.0);
+        require!(revoked > 0, "Nothing to revoke");
+        self.locked_balance = U128(self.locked_balance.0 - revoked);
+        self.unlock_time_sec = current_time_sec;
+        self.revoker_id = None;
+        revoked
+    }
+
+    pub fn extend(&mut self, unlock_time_sec: u32) {
+        require!(self.schedule.is_none(), "Scheduled lock can not be extended");
+        require!(
+            self.unlock_time_sec < unlock_time_sec
+                && nano_to_sec(env::block_timestamp()) < unlock_time_sec,
+            "Invalid unlock_time_sec"
+        );
+        self.unlock_time_sec = unlock_time_sec;
+    }
+
+    /// Adds `amount` to a lock that has not matured, leaving its unlock time as is.
+    pub fn top_up(&mut self, amount: U128) {
+        require!(self.schedule.is_none(), "Scheduled lock can not be topped up");
+        require!(
+            nano_to_sec(env::block_timestamp()) < self.unlock_time_sec,
+            "Lock already matured"
+        );
+        self.locked_balance = U128(self.locked_balance.0 + amount.0);
+    }
+
     pub fn append_lock(&mut self, amount: U128, unlock_time_sec: u32) {
+        require!(self.schedule.is_none(), "Scheduled lock can not be appended");
         require!(
             self.unlock_time_sec <= unlock_time_sec
                 && nano_to_sec(env::block_timestamp()) < unlock_time_sec,
@@ -21,21 +92,48 @@ impl LockInfo {
     }
 }
 
+/// A transfer out of a lock started by `withdraw_all`.
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct ScheduledWithdraw {
+    pub token_id: String,
+    pub lock_id: LockId,
+    pub amount: U128,
+}
+
 #[near(serializers = [borsh, json])]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 pub struct Account {
     pub account_id: AccountId,
-    pub locked_tokens: HashMap<String, LockInfo>,
+    pub locked_tokens: HashMap<String, HashMap<LockId, LockInfo>>,
+    /// NEAR deposited through `storage_deposit` to pay for this account's storage.
+    pub storage_balance: NearToken,
+    /// Part of `storage_balance` paid by the sponsorship pool. It can not be withdrawn
+    /// and goes back to the pool when the account unregisters.
+    pub storage_sponsored: NearToken,
+    /// Bytes of contract storage this account is charged for.
+    pub storage_usage: u64,
+    /// Locked balance history per token, see `get_locked_at`.
+    #[serde(skip)]
+    pub checkpoints: HashMap<String, AccountCheckpoints>,
+    /// Reward pool stakes per lock token.
+    #[serde(skip)]
+    pub rewards: HashMap<String, AccountRewards>,
+    /// Settled rewards per reward token, see `get_unclaimed_rewards`.
+    #[serde(skip)]
+    pub unclaimed_rewards: HashMap<String, U128>,
 }
 
 #[near(serializers = [borsh])]
 pub enum VAccount {
+    V0(AccountV0),
     Current(Account),
 }
 
 impl From<VAccount> for Account {
     fn from(v: VAccount) -> Self {
         match v {
+            VAccount::V0(c) => c.into(),
             VAccount::Current(c) => c,
         }
     }
@@ -52,25 +150,115 @@ impl Account {
         Self {
             account_id: account_id.clone(),
             locked_tokens: HashMap::new(),
+            storage_balance: NearToken::from_yoctonear(0),
+            storage_sponsored: NearToken::from_yoctonear(0),
+            storage_usage: 0,
+            checkpoints: HashMap::new(),
+            rewards: HashMap::new(),
+            unclaimed_rewards: HashMap::new(),
         }
     }
 
-    pub fn add_lock(&mut self, token_id: &String, amount: U128, unlock_time_sec: u32) {
+    pub fn storage_available(&self) -> NearToken {
+        self.storage_balance
+            .saturating_sub(env::storage_byte_cost().saturating_mul(self.storage_usage as u128))
+    }
+
+    pub fn is_storage_covered(&self) -> bool {
+        env::storage_byte_cost().saturating_mul(self.storage_usage as u128) <= self.storage_balance
+    }
+
+    pub fn lock_num(&self) -> usize {
+        self.locked_tokens.values().map(|locks| locks.len()).sum()
+    }
+
+    pub fn add_lock(&mut self, token_id: &str, lock_id: LockId, lock_info: LockInfo) {
         require!(
-            self.locked_tokens.len() < MAX_LOCK_NUM,
+            self.lock_num() < MAX_LOCK_NUM,
             "Exceed MAX_LOCK_NUM"
         );
         require!(
-            nano_to_sec(env::block_timestamp()) < unlock_time_sec,
+            nano_to_sec(env::block_timestamp()) < lock_info.unlock_time_sec,
             "Invalid unlock_time_sec"
         );
-        self.locked_tokens.insert(
-            token_id.clone(),
-            LockInfo {
-                locked_balance: amount,
-                unlock_time_sec,
-            },
+        self.insert_lock(token_id, lock_id, lock_info);
+    }
+
+    /// Takes over a position from another account. Unlike `add_lock` it may be
+    /// unlocked already.
+    pub fn receive_lock(&mut self, token_id: &str, lock_id: LockId, lock_info: LockInfo) {
+        require!(
+            self.lock_num() < MAX_LOCK_NUM,
+            "Exceed MAX_LOCK_NUM"
         );
+        self.insert_lock(token_id, lock_id, lock_info);
+    }
+
+    pub fn get_lock_mut(&mut self, token_id: &str, lock_id: LockId) -> Option<&mut LockInfo> {
+        self.locked_tokens
+            .get_mut(token_id)
+            .and_then(|locks| locks.get_mut(&lock_id))
+    }
+
+    pub fn insert_lock(&mut self, token_id: &str, lock_id: LockId, lock_info: LockInfo) {
+        self.locked_tokens
+            .entry(token_id.to_string())
+            .or_default()
+            .insert(lock_id, lock_info);
+    }
+
+    /// Puts back an amount whose transfer out failed. If the account no longer holds
+    /// the position, an unlocked one is created under `lock_id`.
+    pub fn refund_lock(&mut self, token_id: &str, lock_id: LockId, amount: U128) {
+        if let Some(lock_info) = self.get_lock_mut(token_id, lock_id) {
+            lock_info.locked_balance = U128(lock_info.locked_balance.0 + amount.0);
+        } else {
+            self.insert_lock(
+                token_id,
+                lock_id,
+                LockInfo::new(amount, nano_to_sec(env::block_timestamp())),
+            );
+        }
+    }
+
+    pub fn remove_lock(&mut self, token_id: &str, lock_id: LockId) -> Option<LockInfo> {
+        let locks = self.locked_tokens.get_mut(token_id)?;
+        let lock_info = locks.remove(&lock_id);
+        if locks.is_empty() {
+            self.locked_tokens.remove(token_id);
+        }
+        lock_info
+    }
+
+    /// Ids of all positions, in ascending order.
+    pub fn lock_ids(&self) -> Vec<LockId> {
+        let mut lock_ids: Vec<LockId> = self
+            .locked_tokens
+            .values()
+            .flat_map(|locks| locks.keys().copied())
+            .collect();
+        lock_ids.sort();
+        lock_ids
+    }
+
+    pub fn find_lock(&self, lock_id: LockId) -> Option<(&String, &LockInfo)> {
+        self.locked_tokens
+            .iter()
+            .find_map(|(token_id, locks)| locks.get(&lock_id).map(|lock_info| (token_id, lock_info)))
+    }
+
+    /// Locked balance per token and unlock time.
+    pub fn locked_balances(&self) -> HashMap<String, HashMap<u32, u128>> {
+        self.locked_tokens
+            .iter()
+            .map(|(token_id, locks)| {
+                let mut balances = HashMap::new();
+                for lock_info in locks.values() {
+                    *balances.entry(lock_info.unlock_time_sec).or_default() += lock_info.locked_balance.0;
+                }
+                (token_id.clone(), balances)
+            })
+            .collect()
     }
 }
 
@@ -85,25 +273,130 @@ impl Contract {
     }
 
     pub fn internal_set_account(&mut self, account_id: &AccountId, account: Account) {
+        require!(
+            self.internal_save_account(account_id, account),
+            "INSUFFICIENT STORAGE"
+        );
+    }
+
+    /// Same as `internal_set_account` but lets the account run into storage debt,
+    /// for callbacks and paths that only release funds, which must not fail.
+    pub fn internal_force_set_account(&mut self, account_id: &AccountId, account: Account) {
+        self.internal_save_account(account_id, account);
+    }
+
+    /// Settles the account's rewards, writes it and charges it for the bytes the write
+    /// and its lock NFTs added or released, plus what its token checkpoints added. The
+    /// ve supplies are shared and bounded, so the contract pays for them.
+    /// Returns whether its storage balance still covers its usage.
+    fn internal_save_account(&mut self, account_id: &AccountId, mut account: Account) -> bool {
+        // Legacy accounts are not counted in the token stats until migrated.
+        let (prev_locked_balances, prev_lock_ids) = match self.data().accounts.get(account_id) {
+            Some(VAccount::Current(prev_account)) => (prev_account.locked_balances(), prev_account.lock_ids()),
+            Some(VAccount::V0(_)) => {
+                self.data_mut().legacy_account_num -= 1;
+                (HashMap::new(), vec![])
+            }
+            None => (HashMap::new(), vec![]),
+        };
+        self.internal_settle_rewards(&mut account, &prev_locked_balances);
+        let locked_balances = account.locked_balances();
+        let lock_ids = account.lock_ids();
+        account.record_checkpoints(
+            &prev_locked_balances,
+            &locked_balances,
+            nano_to_sec(env::block_timestamp()),
+        );
+        let initial_storage_usage = env::storage_usage();
+        let v_account: VAccount = account.into();
+        self.data_mut().accounts.insert(account_id, &v_account);
+        let burnt_lock_ids = self.internal_update_lock_owners(account_id, &prev_lock_ids, &lock_ids);
+        let final_storage_usage = env::storage_usage();
+        let checkpoint_storage_usage =
+            self.internal_update_locked_stats(&prev_locked_balances, &locked_balances);
+        self.internal_update_unlock_balances(&prev_locked_balances, &locked_balances);
+        self.internal_clear_lock_approvals(account_id, &burnt_lock_ids);
+
+        let mut account: Account = v_account.into();
+        account.storage_usage += checkpoint_storage_usage;
+        if final_storage_usage >= initial_storage_usage {
+            account.storage_usage += final_storage_usage - initial_storage_usage;
+        } else {
+            account.storage_usage = account
+                .storage_usage
+                .saturating_sub(initial_storage_usage - final_storage_usage);
+        }
+        let is_storage_covered = account.is_storage_covered();
+        // Fixed-size fields only changed, so this write does not change the storage usage.
         self.data_mut().accounts.insert(account_id, &account.into());
+        is_storage_covered
+    }
+
+    pub fn internal_remove_account(&mut self, account_id: &AccountId) -> Option<Account> {
+        let v_account = self.data_mut().accounts.remove(account_id)?;
+        let is_counted = matches!(v_account, VAccount::Current(_));
+        let mut account: Account = v_account.into();
+        let locked_balances = if is_counted {
+            account.locked_balances()
+        } else {
+            self.data_mut().legacy_account_num -= 1;
+            HashMap::new()
+        };
+        self.internal_update_unlock_balances(&locked_balances, &HashMap::new());
+        self.internal_update_locked_stats(&locked_balances, &HashMap::new());
+        let burnt_lock_ids = self.internal_update_lock_owners(account_id, &account.lock_ids(), &[]);
+        self.internal_clear_lock_approvals(account_id, &burnt_lock_ids);
+        // Settle as if the locks were gone, so the account leaves the reward pools.
+        let locked_tokens = std::mem::take(&mut account.locked_tokens);
+        self.internal_settle_rewards(&mut account, &locked_balances);
+        self.internal_move_rewards_to_lostfound(account_id, &mut account);
+        account.locked_tokens = locked_tokens;
+        Some(account)
+    }
+
+    /// Credits back a failed transfer out of `lock_id`. A position that is gone by now
+    /// is recreated under a fresh id, as the old one may still be held elsewhere.
+    pub fn internal_refund_lock(&mut self, account: &mut Account, token_id: &str, lock_id: LockId, amount: U128) -> LockId {
+        let lock_id = if account.get_lock_mut(token_id, lock_id).is_some() {
+            lock_id
+        } else {
+            self.internal_next_lock_id()
+        };
+        account.refund_lock(token_id, lock_id, amount);
+        lock_id
+    }
+
+    pub fn internal_next_lock_id(&mut self) -> LockId {
+        let lock_id = self.data().next_lock_id;
+        self.data_mut().next_lock_id += 1;
+        lock_id
     }
 }
 
 #[near]
 impl Contract {
-    
+    /// Sends the tokens to `receiver_id`, which defaults to the lock owner. With `msg`
+    /// they go through `ft_transfer_call`/`mft_transfer_call`, and whatever the receiver
+    /// does not use is put back into the lock.
     #[payable]
-    pub fn withdraw(&mut self, token_id: String, amount: Option<U128>) {
+    pub fn withdraw(
+        &mut self,
+        token_id: String,
+        lock_id: LockId,
+        amount: Option<U128>,
+        receiver_id: Option<AccountId>,
+        msg: Option<String>,
+    ) {
         assert_one_yocto();
+        self.assert_running(Some(&token_id), Operation::Withdraw);
         let account_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&account_id);
 
-        if let Some(mut lock_info) = account.locked_tokens.remove(&token_id) {
-            require!(
-                lock_info.unlock_time_sec <= nano_to_sec(env::block_timestamp()),
-                "Token still locked"
-            );
-            let amount = amount.unwrap_or(lock_info.locked_balance);
+        if let Some(mut lock_info) = account.remove_lock(&token_id, lock_id) {
+            let withdrawable = lock_info.withdrawable_balance(nano_to_sec(env::block_timestamp()));
+            require!(withdrawable > 0, "Token still locked");
+            let amount = amount.unwrap_or(U128(withdrawable));
+            require!(amount.0 <= withdrawable, "Lock balance not enough");
             lock_info.locked_balance = U128(
                 lock_info
                     .locked_balance
@@ -112,29 +405,46 @@ impl Contract {
                     .expect("Lock balance not enough"),
             );
             if lock_info.locked_balance.0 > 0 {
-                account.locked_tokens.insert(token_id.clone(), lock_info);
+                account.insert_lock(&token_id, lock_id, lock_info);
             }
-            self.internal_set_account(&account_id, account);
-            self.transfer_token(&account_id, token_id.clone(), amount);
+            self.internal_force_set_account(&account_id, account);
+            let receiver_id = receiver_id.filter(|receiver_id| *receiver_id != account_id);
+            self.transfer_token(
+                &account_id,
+                receiver_id.as_ref().unwrap_or(&account_id),
+                token_id.clone(),
+                lock_id,
+                amount,
+                msg,
+            );
             Event::WithdrawStarted {
                 account_id: &account_id,
                 token_id: &token_id,
+                lock_id,
                 amount: &amount,
+                receiver_id: receiver_id.as_ref(),
             }
             .emit();
         } else {
-            env::panic_str("Invalid token_id");
+            env::panic_str("Invalid lock");
         }
     }
 
     #[payable]
-    pub fn burn(&mut self, token_id: String, amount: Option<U128>) {
+    pub fn burn(&mut self, token_id: String, lock_id: LockId, amount: Option<U128>) {
         assert_one_yocto();
+        self.assert_running(Some(&token_id), Operation::Burn);
         let account_id = env::predecessor_account_id();
         let mut account = self.internal_unwrap_account(&account_id);
 
-        if let Some(mut lock_info) = account.locked_tokens.remove(&token_id) {
+        if let Some(mut lock_info) = account.remove_lock(&token_id, lock_id) {
             let amount = amount.unwrap_or(lock_info.locked_balance);
+            if lock_info.revoker_id.is_some() {
+                require!(
+                    amount.0 <= lock_info.withdrawable_balance(nano_to_sec(env::block_timestamp())),
+                    "Can not burn unvested revocable lock"
+                );
+            }
             lock_info.locked_balance = U128(
                 lock_info
                     .locked_balance
@@ -143,65 +453,248 @@ impl Contract {
                     .expect("Lock balance not enough"),
             );
             if lock_info.locked_balance.0 > 0 {
-                account.locked_tokens.insert(token_id.clone(), lock_info);
+                account.insert_lock(&token_id, lock_id, lock_info);
             }
-            self.internal_set_account(&account_id, account);
-            self.burn_token(&account_id, token_id.clone(), amount);
+            self.internal_force_set_account(&account_id, account);
+            self.burn_token(&account_id, token_id.clone(), lock_id, amount);
             Event::BurnStarted {
                 account_id: &account_id,
                 token_id: &token_id,
+                lock_id,
+                amount: &amount,
+            }
+            .emit();
+        } else {
+            env::panic_str("Invalid lock");
+        }
+    }
+
+    /// Pushes the unlock time of the caller's lock to `new_unlock_time_sec`, which must be
+    /// later than the current one and in the future.
+    #[payable]
+    pub fn extend_lock(&mut self, token_id: String, lock_id: LockId, new_unlock_time_sec: u32) {
+        assert_one_yocto();
+        self.assert_running(Some(&token_id), Operation::Lock);
+        self.assert_lock_duration(&token_id, new_unlock_time_sec);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let lock_info = account.get_lock_mut(&token_id, lock_id).expect("Invalid lock");
+        let prev_unlock_time_sec = lock_info.unlock_time_sec;
+        lock_info.extend(new_unlock_time_sec);
+        self.internal_set_account(&account_id, account);
+        Event::LockExtended {
+            account_id: &account_id,
+            token_id: &token_id,
+            lock_id,
+            prev_unlock_time_sec,
+            unlock_time_sec: new_unlock_time_sec,
+        }
+        .emit();
+    }
+
+    /// Withdraws everything withdrawable from the caller's locks of `token_ids`, or of all
+    /// its tokens, with one transfer per lock. Locks are processed in token and lock id
+    /// order for as long as the attached gas lasts; call it again for the rest. Tokens
+    /// with withdrawals paused are skipped unless listed explicitly.
+    #[payable]
+    pub fn withdraw_all(&mut self, token_ids: Option<Vec<String>>) -> Vec<ScheduledWithdraw> {
+        assert_one_yocto();
+        self.assert_running(None, Operation::Withdraw);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let current_time_sec = nano_to_sec(env::block_timestamp());
+        let is_explicit = token_ids.is_some();
+        let mut token_ids = token_ids.unwrap_or_else(|| account.locked_tokens.keys().cloned().collect());
+        token_ids.sort();
+        token_ids.dedup();
+
+        let gas_per_transfer = GAS_FOR_TOKEN_TRANSFER
+            .saturating_add(GAS_FOR_AFTER_TOKEN_TRANSFER)
+            .saturating_add(GAS_FOR_WITHDRAW_ALL_ITEM);
+        let mut gas_left = env::prepaid_gas()
+            .saturating_sub(env::used_gas())
+            .saturating_sub(GAS_FOR_WITHDRAW_ALL);
+        require!(gas_left >= gas_per_transfer, "Not enough gas");
+        let mut withdrawals = vec![];
+        'tokens: for token_id in token_ids {
+            if is_explicit {
+                self.assert_running(Some(&token_id), Operation::Withdraw);
+            } else if !self.is_running(Some(&token_id), Operation::Withdraw) {
+                continue;
+            }
+            let Some(locks) = account.locked_tokens.get(&token_id) else {
+                continue;
+            };
+            let mut lock_ids: Vec<LockId> = locks.keys().copied().collect();
+            lock_ids.sort();
+            for lock_id in lock_ids {
+                let lock_info = account.get_lock_mut(&token_id, lock_id).unwrap();
+                let amount = lock_info.withdrawable_balance(current_time_sec);
+                if amount == 0 {
+                    continue;
+                }
+                if gas_left < gas_per_transfer {
+                    break 'tokens;
+                }
+                gas_left = gas_left.saturating_sub(gas_per_transfer);
+                lock_info.locked_balance = U128(lock_info.locked_balance.0 - amount);
+                if lock_info.locked_balance.0 == 0 {
+                    account.remove_lock(&token_id, lock_id);
+                }
+                withdrawals.push(ScheduledWithdraw {
+                    token_id: token_id.clone(),
+                    lock_id,
+                    amount: U128(amount),
+                });
+            }
+        }
+        require!(!withdrawals.is_empty(), "Nothing to withdraw");
+        self.internal_force_set_account(&account_id, account);
+        for withdrawal in withdrawals.iter() {
+            self.transfer_token(
+                &account_id,
+                &account_id,
+                withdrawal.token_id.clone(),
+                withdrawal.lock_id,
+                withdrawal.amount,
+                None,
+            );
+            Event::WithdrawStarted {
+                account_id: &account_id,
+                token_id: &withdrawal.token_id,
+                lock_id: withdrawal.lock_id,
+                amount: &withdrawal.amount,
+                receiver_id: None,
+            }
+            .emit();
+        }
+        withdrawals
+    }
+
+    /// Called by the revoker of a grant. Sends the unvested part of the lock back to the
+    /// revoker and leaves the vested part claimable by `account_id`.
+    #[payable]
+    pub fn revoke(&mut self, account_id: AccountId, token_id: String, lock_id: LockId) {
+        assert_one_yocto();
+        self.assert_running(Some(&token_id), Operation::Withdraw);
+        let revoker_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+
+        if let Some(mut lock_info) = account.remove_lock(&token_id, lock_id) {
+            require!(
+                lock_info.revoker_id.as_ref() == Some(&revoker_id),
+                "NOT ALLOWED"
+            );
+            let amount = U128(lock_info.revoke(nano_to_sec(env::block_timestamp())));
+            if lock_info.locked_balance.0 > 0 {
+                account.insert_lock(&token_id, lock_id, lock_info);
+            }
+            self.internal_force_set_account(&account_id, account);
+            self.internal_add_pending_transfer(&token_id, amount);
+            token_transfer(&token_id, &revoker_id, amount).then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                    .after_revoke_transfer(account_id.clone(), revoker_id.clone(), token_id.clone(), lock_id, amount),
+            );
+            Event::LockRevoked {
+                account_id: &account_id,
+                revoker_id: &revoker_id,
+                token_id: &token_id,
+                lock_id,
                 amount: &amount,
             }
             .emit();
         } else {
-            env::panic_str("Invalid token_id");
+            env::panic_str("Invalid lock");
         }
     }
 
+    /// Returns the amount that left the contract. Anything else goes back to
+    /// `account_id`, or to its lost-and-found when it is gone.
     #[private]
     pub fn after_token_transfer(
         &mut self,
         account_id: AccountId,
         token_id: String,
+        lock_id: LockId,
         amount: U128,
-    ) -> bool {
-        let promise_success = is_promise_success();
-        if !promise_success {
+        is_call: bool,
+    ) -> U128 {
+        let used = match env::promise_result(0) {
+            // A transfer call resolves to the amount the receiver kept.
+            PromiseResult::Successful(value) if is_call => serde_json::from_slice::<U128>(&value)
+                .map_or(amount.0, |used| std::cmp::min(used.0, amount.0)),
+            PromiseResult::Successful(_) => amount.0,
+            PromiseResult::Failed => 0,
+        };
+        self.internal_finish_pending_transfer(&token_id, amount);
+        if used > 0 {
+            Event::WithdrawSucceeded {
+                account_id: &account_id,
+                token_id: &token_id,
+                lock_id,
+                amount: &U128(used),
+            }
+            .emit();
+        }
+        let refund = U128(amount.0 - used);
+        if refund.0 > 0 {
             if let Some(mut account) = self.internal_get_account(&account_id) {
-                if let Some(lock_info) = account.locked_tokens.get_mut(&token_id.to_string()) {
-                    lock_info.locked_balance = U128(lock_info.locked_balance.0 + amount.0);
-                } else {
-                    account.locked_tokens.insert(
-                        token_id.clone(),
-                        LockInfo {
-                            locked_balance: amount,
-                            unlock_time_sec: nano_to_sec(env::block_timestamp()),
-                        },
-                    );
-                }
-                self.internal_set_account(&account_id, account);
+                let lock_id = self.internal_refund_lock(&mut account, &token_id, lock_id, refund);
+                self.internal_force_set_account(&account_id, account);
                 Event::WithdrawFailed {
                     account_id: &account_id,
                     token_id: &token_id,
-                    amount: &amount,
+                    lock_id,
+                    amount: &refund,
                 }
                 .emit();
             } else {
+                self.internal_add_lostfound(&account_id, &token_id, refund);
                 Event::WithdrawLostfound {
                     account_id: &account_id,
                     token_id: &token_id,
-                    amount: &amount,
+                    lock_id,
+                    amount: &refund,
                 }
                 .emit();
             }
+        }
+        U128(used)
+    }
+
+    /// The revoked part belongs to `revoker_id` already, so a failed transfer goes to
+    /// its lost-and-found rather than back into the lock.
+    #[private]
+    pub fn after_revoke_transfer(
+        &mut self,
+        account_id: AccountId,
+        revoker_id: AccountId,
+        token_id: String,
+        lock_id: LockId,
+        amount: U128,
+    ) -> bool {
+        let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&token_id, amount);
+        if promise_success {
+            Event::LockRevokeSucceeded {
+                account_id: &account_id,
+                revoker_id: &revoker_id,
+                token_id: &token_id,
+                lock_id,
+                amount: &amount,
+            }
+            .emit();
         } else {
-            Event::WithdrawSucceeded {
+            self.internal_add_lostfound(&revoker_id, &token_id, amount);
+            Event::LockRevokeFailed {
                 account_id: &account_id,
+                revoker_id: &revoker_id,
                 token_id: &token_id,
+                lock_id,
                 amount: &amount,
             }
             .emit();
-            
         }
         promise_success
     }
@@ -211,99 +704,80 @@ impl Contract {
         &mut self,
         account_id: AccountId,
         token_id: String,
+        lock_id: LockId,
         amount: U128,
     ) -> bool {
         let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&token_id, amount);
         if !promise_success {
             if let Some(mut account) = self.internal_get_account(&account_id) {
-                if let Some(lock_info) = account.locked_tokens.get_mut(&token_id.to_string()) {
-                    lock_info.locked_balance = U128(lock_info.locked_balance.0 + amount.0);
-                } else {
-                    account.locked_tokens.insert(
-                        token_id.clone(),
-                        LockInfo {
-                            locked_balance: amount,
-                            unlock_time_sec: nano_to_sec(env::block_timestamp()),
-                        },
-                    );
-                }
-                self.internal_set_account(&account_id, account);
+                let lock_id = self.internal_refund_lock(&mut account, &token_id, lock_id, amount);
+                self.internal_force_set_account(&account_id, account);
                 Event::BurnFailed {
                     account_id: &account_id,
                     token_id: &token_id,
+                    lock_id,
                     amount: &amount,
                 }
                 .emit();
             } else {
+                self.internal_add_lostfound(&account_id, &token_id, amount);
                 Event::BurnLostfound {
                     account_id: &account_id,
                     token_id: &token_id,
+                    lock_id,
                     amount: &amount,
                 }
                 .emit();
             }
         } else {
+            self.internal_update_token_stats(&token_id, |token_stats| {
+                token_stats.total_burned = U128(token_stats.total_burned.0 + amount.0);
+            });
             Event::BurnSucceeded {
                 account_id: &account_id,
                 token_id: &token_id,
+                lock_id,
                 amount: &amount,
             }
             .emit();
-            
         }
         promise_success
     }
 }
 
 impl Contract {
-    pub fn transfer_token(&self, account_id: &AccountId, token_id: String, amount: U128) {
-        let (contract_id, mft_token_id) = parse_token_id(&token_id);
-        if let Some(mft_token_id) = mft_token_id {
-            ext_multi_fungible_token::ext(contract_id.clone())
-                .with_attached_deposit(NearToken::from_yoctonear(1))
-                .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
-                .mft_transfer(mft_token_id, account_id.clone(), amount, None)
-                .then(
-                    Self::ext(env::current_account_id())
-                        .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
-                        .after_token_transfer(account_id.clone(), token_id.clone(), amount),
-                )
-        } else {
-            ext_fungible_token::ext(contract_id.clone())
-                .with_attached_deposit(NearToken::from_yoctonear(1))
-                .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
-                .ft_transfer(account_id.clone(), amount, None)
-                .then(
-                    Self::ext(env::current_account_id())
-                        .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
-                        .after_token_transfer(account_id.clone(), token_id.to_string(), amount),
-                )
+    /// Sends `amount` taken out of `account_id`'s lock `lock_id` to `receiver_id`,
+    /// with a transfer call when `msg` is given.
+    pub fn transfer_token(
+        &mut self,
+        account_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: String,
+        lock_id: LockId,
+        amount: U128,
+        msg: Option<String>,
+    ) {
+        self.internal_add_pending_transfer(&token_id, amount);
+        let is_call = msg.is_some();
+        let transfer = match msg {
+            Some(msg) => token_transfer_call(&token_id, receiver_id, amount, msg),
+            None => token_transfer(&token_id, receiver_id, amount),
         };
+        transfer.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                .after_token_transfer(account_id.clone(), token_id, lock_id, amount, is_call),
+        );
     }
 
-    pub fn burn_token(&self, account_id: &AccountId, token_id: String, amount: U128) {
+    pub fn burn_token(&mut self, account_id: &AccountId, token_id: String, lock_id: LockId, amount: U128) {
         let burn_account_id = self.data().burn_account_id.clone().expect("Missing burn_account_id");
-        let (contract_id, mft_token_id) = parse_token_id(&token_id);
-        if let Some(mft_token_id) = mft_token_id {
-            ext_multi_fungible_token::ext(contract_id.clone())
-                .with_attached_deposit(NearToken::from_yoctonear(1))
-                .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
-                .mft_transfer(mft_token_id, burn_account_id, amount, None)
-                .then(
-                    Self::ext(env::current_account_id())
-                        .with_static_gas(GAS_FOR_AFTER_TOKEN_BURN)
-                        .after_token_burn(account_id.clone(), token_id.clone(), amount),
-                )
-        } else {
-            ext_fungible_token::ext(contract_id.clone())
-                .with_attached_deposit(NearToken::from_yoctonear(1))
-                .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
-                .ft_transfer(burn_account_id, amount, None)
-                .then(
-                    Self::ext(env::current_account_id())
-                        .with_static_gas(GAS_FOR_AFTER_TOKEN_BURN)
-                        .after_token_burn(account_id.clone(), token_id.to_string(), amount),
-                )
-        };
+        self.internal_add_pending_transfer(&token_id, amount);
+        token_transfer(&token_id, &burn_account_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_BURN)
+                .after_token_burn(account_id.clone(), token_id, lock_id, amount),
+        );
     }
 }
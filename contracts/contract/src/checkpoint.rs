@@ -0,0 +1,123 @@
+use crate::*;
+
+/// Per token, an account keeps at most this many checkpoints. Older ones are evicted.
+pub const MAX_ACCOUNT_CHECKPOINT_NUM: usize = 32;
+
+/// Locked balance from `timestamp_sec` on, until the next checkpoint.
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct Checkpoint {
+    pub timestamp_sec: u32,
+    pub balance: U128,
+}
+
+#[derive(Default)]
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct AccountCheckpoints {
+    pub checkpoints: Vec<Checkpoint>,
+    /// Whether checkpoints were evicted, so nothing is known before the first one.
+    pub is_truncated: bool,
+}
+
+impl AccountCheckpoints {
+    fn push(&mut self, timestamp_sec: u32, balance: u128) {
+        match self.checkpoints.last_mut() {
+            Some(last) if last.timestamp_sec == timestamp_sec => last.balance = U128(balance),
+            _ => {
+                if self.checkpoints.len() == MAX_ACCOUNT_CHECKPOINT_NUM {
+                    self.checkpoints.remove(0);
+                    self.is_truncated = true;
+                }
+                self.checkpoints.push(Checkpoint {
+                    timestamp_sec,
+                    balance: U128(balance),
+                });
+            }
+        }
+    }
+
+    /// `None` if `timestamp_sec` is before the evicted part of the history.
+    pub fn balance_at(&self, timestamp_sec: u32) -> Option<u128> {
+        let index = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.timestamp_sec <= timestamp_sec);
+        if index > 0 {
+            Some(self.checkpoints[index - 1].balance.0)
+        } else if self.is_truncated {
+            None
+        } else {
+            Some(0)
+        }
+    }
+}
+
+impl Account {
+    /// Records a checkpoint for each token whose locked total changed from `prev` to `current`.
+    pub fn record_checkpoints(
+        &mut self,
+        prev: &HashMap<String, HashMap<u32, u128>>,
+        current: &HashMap<String, HashMap<u32, u128>>,
+        current_time_sec: u32,
+    ) {
+        let total = |balances: Option<&HashMap<u32, u128>>| -> u128 {
+            balances.map_or(0, |balances| balances.values().sum())
+        };
+        for token_id in prev.keys().chain(current.keys().filter(|token_id| !prev.contains_key(*token_id))) {
+            let current_balance = total(current.get(token_id));
+            if total(prev.get(token_id)) != current_balance {
+                self.checkpoints
+                    .entry(token_id.clone())
+                    .or_default()
+                    .push(current_time_sec, current_balance);
+            }
+        }
+    }
+}
+
+impl Contract {
+    /// Appends to the token's checkpoints and returns the bytes this added. They are
+    /// never evicted, so the account whose write added them is charged for them.
+    pub fn internal_push_token_checkpoint(&mut self, token_id: &String, balance: u128) -> u64 {
+        let initial_storage_usage = env::storage_usage();
+        let timestamp_sec = nano_to_sec(env::block_timestamp());
+        let mut checkpoints = self.data().token_checkpoints.get(token_id).unwrap_or_else(|| {
+            Vector::new(StorageKey::TokenCheckpointsItem {
+                token_id: token_id.clone(),
+            })
+        });
+        let checkpoint = Checkpoint {
+            timestamp_sec,
+            balance: U128(balance),
+        };
+        match checkpoints.len().checked_sub(1) {
+            Some(last_index) if checkpoints.get(last_index).unwrap().timestamp_sec == timestamp_sec => {
+                checkpoints.replace(last_index, &checkpoint);
+            }
+            _ => checkpoints.push(&checkpoint),
+        }
+        self.data_mut().token_checkpoints.insert(token_id, &checkpoints);
+        env::storage_usage().saturating_sub(initial_storage_usage)
+    }
+
+    pub fn internal_get_total_locked_at(&self, token_id: &String, timestamp_sec: u32) -> u128 {
+        let Some(checkpoints) = self.data().token_checkpoints.get(token_id) else {
+            return 0;
+        };
+        // Finds the number of checkpoints at or before `timestamp_sec`.
+        let (mut low, mut high) = (0, checkpoints.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if checkpoints.get(mid).unwrap().timestamp_sec <= timestamp_sec {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            0
+        } else {
+            checkpoints.get(low - 1).unwrap().balance.0
+        }
+    }
+}
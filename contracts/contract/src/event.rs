@@ -1,5 +1,5 @@
 use crate::*;
-use near_sdk::serde::Serialize;
+use near_sdk::{json_types::Base58CryptoHash, serde::Serialize};
 
 const EVENT_STANDARD: &str = "token-locker";
 const EVENT_STANDARD_VERSION: &str = "1.0.0";
@@ -12,61 +12,291 @@ pub enum Event<'a> {
     LockedToken {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
         unlock_time_sec: u32,
     },
+    LockedTokenFor {
+        funder_id: &'a AccountId,
+        account_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+        unlock_time_sec: u32,
+    },
+    LockedVesting {
+        account_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+        start_sec: u32,
+        cliff_sec: u32,
+        end_sec: u32,
+    },
+    LockedSteps {
+        account_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+        steps: &'a Vec<UnlockStep>,
+    },
+    LockedGrant {
+        funder_id: &'a AccountId,
+        account_id: &'a AccountId,
+        revoker_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+        start_sec: u32,
+        cliff_sec: u32,
+        end_sec: u32,
+    },
+    LockRevoked {
+        account_id: &'a AccountId,
+        revoker_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+    },
+    LockRevokeSucceeded {
+        account_id: &'a AccountId,
+        revoker_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+    },
+    LockRevokeFailed {
+        account_id: &'a AccountId,
+        revoker_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+    },
     AppendToken {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
         unlock_time_sec: u32,
     },
+    LockExtended {
+        account_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        prev_unlock_time_sec: u32,
+        unlock_time_sec: u32,
+    },
     WithdrawStarted {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
+        /// Set when the tokens go to someone other than the lock owner.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        receiver_id: Option<&'a AccountId>,
     },
     WithdrawSucceeded {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
     },
     WithdrawFailed {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
     },
     WithdrawLostfound {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+    },
+    LockTransferred {
+        account_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        receiver_lock_id: LockId,
         amount: &'a U128,
     },
     BurnStarted {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
     },
     BurnSucceeded {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
     },
     BurnFailed {
         account_id: &'a AccountId,
         token_id: &'a String,
+        lock_id: LockId,
         amount: &'a U128,
     },
     BurnLostfound {
+        account_id: &'a AccountId,
+        token_id: &'a String,
+        lock_id: LockId,
+        amount: &'a U128,
+    },
+    LostfoundClaimStarted {
         account_id: &'a AccountId,
         token_id: &'a String,
         amount: &'a U128,
     },
+    LostfoundSwept {
+        account_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        token_id: &'a String,
+        amount: &'a U128,
+    },
+    LostfoundTransferSucceeded {
+        account_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        token_id: &'a String,
+        amount: &'a U128,
+    },
+    LostfoundTransferFailed {
+        account_id: &'a AccountId,
+        receiver_id: &'a AccountId,
+        token_id: &'a String,
+        amount: &'a U128,
+    },
     AccountRegister {
         account_id: &'a AccountId,
     },
     AccountUnregister {
         account_id: &'a AccountId,
     },
+    StorageSponsored {
+        account_id: &'a AccountId,
+        amount: &'a NearToken,
+    },
+    StorageSponsorDeposit {
+        amount: &'a NearToken,
+    },
+    StorageSponsorWithdraw {
+        amount: &'a NearToken,
+    },
+    OwnerProposed {
+        owner_id: &'a AccountId,
+        pending_owner_id: &'a AccountId,
+    },
+    OwnerProposalCancelled {
+        owner_id: &'a AccountId,
+        pending_owner_id: &'a AccountId,
+    },
+    OwnershipTransferred {
+        old_owner_id: &'a AccountId,
+        new_owner_id: &'a AccountId,
+    },
+    AdminActionProposed {
+        action: &'a QueuedAdminAction,
+    },
+    AdminActionExecuted {
+        action: &'a QueuedAdminAction,
+    },
+    AdminActionCancelled {
+        action: &'a QueuedAdminAction,
+    },
+    UpgradeStaged {
+        code_hash: &'a Base58CryptoHash,
+        has_code: bool,
+        deployable_time_sec: u32,
+    },
+    UpgradeCancelled {
+        code_hash: &'a Base58CryptoHash,
+    },
+    UpgradeDeployed {
+        code_hash: &'a Base58CryptoHash,
+    },
+    RoleGranted {
+        account_id: &'a AccountId,
+        role: Role,
+    },
+    RoleRevoked {
+        account_id: &'a AccountId,
+        role: Role,
+    },
+    Paused {
+        by: &'a AccountId,
+        token_id: Option<&'a String>,
+        operations: &'a Vec<Operation>,
+    },
+    Resumed {
+        by: &'a AccountId,
+        token_id: Option<&'a String>,
+        operations: &'a Vec<Operation>,
+    },
+    TokenConfigUpdated {
+        token_id: &'a String,
+        config: &'a TokenConfig,
+    },
+    ExcessSweepStarted {
+        token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
+    ExcessSweepSucceeded {
+        token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
+    ExcessSweepFailed {
+        token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
+    RewardsFunded {
+        funder_id: &'a AccountId,
+        lock_token_id: &'a String,
+        reward_token_id: &'a String,
+        amount: &'a U128,
+        duration_sec: u32,
+        period_finish_sec: u32,
+    },
+    RewardsClaimStarted {
+        account_id: &'a AccountId,
+        reward_token_id: &'a String,
+        amount: &'a U128,
+    },
+    RewardsClaimSucceeded {
+        account_id: &'a AccountId,
+        reward_token_id: &'a String,
+        amount: &'a U128,
+    },
+    RewardsClaimFailed {
+        account_id: &'a AccountId,
+        reward_token_id: &'a String,
+        amount: &'a U128,
+    },
+    RewardsLostfound {
+        account_id: &'a AccountId,
+        reward_token_id: &'a String,
+        amount: &'a U128,
+    },
+    RewardsReclaimStarted {
+        lock_token_id: &'a String,
+        reward_token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
+    RewardsReclaimSucceeded {
+        reward_token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
+    RewardsReclaimFailed {
+        reward_token_id: &'a String,
+        receiver_id: &'a AccountId,
+        amount: &'a U128,
+    },
 }
 
 impl Event<'_> {
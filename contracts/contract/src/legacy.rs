@@ -7,7 +7,7 @@ pub struct ContractDataV1000 {
     token_white_list: UnorderedSet<AccountId>,
 }
 
-impl From<ContractDataV1000> for ContractData {
+impl From<ContractDataV1000> for ContractDataV1001 {
     fn from(a: ContractDataV1000) -> Self {
         let ContractDataV1000 {
             owner_id,
@@ -22,3 +22,138 @@ impl From<ContractDataV1000> for ContractData {
         }
     }
 }
+
+#[near(serializers = [borsh])]
+pub struct ContractDataV1001 {
+    owner_id: AccountId,
+    accounts: UnorderedMap<AccountId, VAccount>,
+    token_white_list: UnorderedSet<AccountId>,
+    burn_account_id: Option<AccountId>,
+}
+
+impl From<ContractDataV1001> for ContractData {
+    fn from(a: ContractDataV1001) -> Self {
+        let ContractDataV1001 {
+            owner_id,
+            accounts,
+            token_white_list,
+            burn_account_id,
+        } = a;
+        // Every account is still stored as `AccountV0` at this point.
+        let legacy_account_num = accounts.len();
+        Self {
+            owner_id,
+            pending_owner_id: None,
+            accounts,
+            token_white_list,
+            burn_account_id,
+            next_lock_id: 0,
+            auto_register: false,
+            storage_sponsor_balance: NearToken::from_yoctonear(0),
+            lostfound: UnorderedMap::new(StorageKey::Lostfound),
+            token_stats: UnorderedMap::new(StorageKey::TokenStats),
+            roles: UnorderedMap::new(StorageKey::Roles),
+            paused_operations: vec![],
+            token_paused_operations: UnorderedMap::new(StorageKey::TokenPausedOperations),
+            admin_action_delay_sec: DEFAULT_ADMIN_ACTION_DELAY_SEC,
+            next_admin_action_id: 0,
+            admin_actions: UnorderedMap::new(StorageKey::AdminActions),
+            staged_upgrade: None,
+            token_configs: UnorderedMap::new(StorageKey::TokenConfigs),
+            ve_supplies: UnorderedMap::new(StorageKey::VeSupplies),
+            token_checkpoints: UnorderedMap::new(StorageKey::TokenCheckpoints),
+            reward_pools: UnorderedMap::new(StorageKey::RewardPools),
+            lock_owners: UnorderedMap::new(StorageKey::LockOwners),
+            next_approval_id: 0,
+            lock_approvals: UnorderedMap::new(StorageKey::LockApprovals),
+            legacy_account_num,
+        }
+    }
+}
+
+#[near(serializers = [borsh])]
+pub struct LockInfoV0 {
+    pub locked_balance: U128,
+    pub unlock_time_sec: u32,
+}
+
+impl From<LockInfoV0> for LockInfo {
+    fn from(a: LockInfoV0) -> Self {
+        let LockInfoV0 {
+            locked_balance,
+            unlock_time_sec,
+        } = a;
+        Self::new(locked_balance, unlock_time_sec)
+    }
+}
+
+#[near(serializers = [borsh])]
+pub struct AccountV0 {
+    pub account_id: AccountId,
+    pub locked_tokens: HashMap<String, LockInfoV0>,
+}
+
+/// Bytes an `AccountV0` record takes in `accounts`: the key index entry, the key and the
+/// value, each stored under the map's one-byte prefix plus a one-byte suffix and paying
+/// the per-record overhead.
+fn legacy_account_storage_usage(a: &AccountV0) -> u64 {
+    const STORAGE_RECORD_OVERHEAD: u64 = 40;
+    const PREFIX_LEN: u64 = 2;
+    const INDEX_LEN: u64 = 8;
+    let key_len = near_sdk::borsh::object_length(&a.account_id).unwrap() as u64;
+    // One more byte for the `VAccount` variant.
+    let value_len = 1 + near_sdk::borsh::object_length(a).unwrap() as u64;
+    (PREFIX_LEN + key_len + INDEX_LEN + STORAGE_RECORD_OVERHEAD)
+        + (PREFIX_LEN + INDEX_LEN + key_len + STORAGE_RECORD_OVERHEAD)
+        + (PREFIX_LEN + INDEX_LEN + value_len + STORAGE_RECORD_OVERHEAD)
+}
+
+impl From<AccountV0> for Account {
+    fn from(a: AccountV0) -> Self {
+        let storage_usage = legacy_account_storage_usage(&a);
+        let AccountV0 {
+            account_id,
+            locked_tokens,
+        } = a;
+        let locked_tokens: HashMap<String, HashMap<LockId, LockInfo>> = locked_tokens
+            .into_iter()
+            .map(|(token_id, lock_info)| {
+                let lock_id = legacy_lock_id(&account_id, &token_id);
+                (token_id, HashMap::from([(lock_id, lock_info.into())]))
+            })
+            .collect();
+        // Nothing is known about balances before the upgrade.
+        let checkpoints = locked_tokens
+            .keys()
+            .map(|token_id| {
+                let checkpoints = AccountCheckpoints {
+                    checkpoints: vec![],
+                    is_truncated: true,
+                };
+                (token_id.clone(), checkpoints)
+            })
+            .collect();
+        // These accounts paid the flat registration fee, which their existing record is
+        // charged against like any other account's.
+        Self {
+            account_id,
+            locked_tokens,
+            storage_balance: STORAGE_BALANCE_MIN_BOUND,
+            storage_sponsored: NearToken::from_yoctonear(0),
+            storage_usage,
+            checkpoints,
+            rewards: HashMap::new(),
+            unclaimed_rewards: HashMap::new(),
+        }
+    }
+}
+
+/// AccountV0 held a single lock per token. Its position id is derived from the account
+/// and the token, so it stays the same without rewriting every account during migration.
+/// Bit 52 keeps it clear of ids handed out by `next_lock_id` while staying JSON-safe.
+pub fn legacy_lock_id(account_id: &AccountId, token_id: &str) -> LockId {
+    let hash = env::sha256(format!("{}:{}", account_id, token_id).as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    (1 << 52) | (u64::from_le_bytes(bytes) & ((1 << 52) - 1))
+}
@@ -1,23 +1,47 @@
 use near_sdk::{
-    assert_one_yocto, borsh::BorshSerialize, collections::{UnorderedMap, UnorderedSet}, env, is_promise_success,
-    json_types::U128, log, near, require, serde_json::{self, json}, AccountId, BorshStorageKey,
-    Gas, NearToken, PanicOnDefault, Promise, PromiseOrValue,
+    assert_one_yocto, borsh::BorshSerialize, collections::{LookupMap, UnorderedMap, UnorderedSet, Vector}, env, is_promise_success,
+    json_types::U128, log, CryptoHash, near, require, serde_json::{self, json}, AccountId, BorshStorageKey,
+    Gas, NearToken, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
 };
+use std::collections::HashMap;
 
 mod account;
+mod checkpoint;
 mod event;
 mod storage;
+mod timelock;
+mod token_config;
 mod token_receiver;
+mod transfer;
 mod legacy;
+mod lostfound;
+mod nft;
+mod pause;
+mod rewards;
+mod role;
+mod schedule;
+mod stats;
 mod upgrade;
 mod utils;
+mod ve;
 mod view;
 pub use account::*;
+pub use checkpoint::*;
 pub use legacy::*;
+pub use lostfound::*;
+pub use pause::*;
+pub use rewards::*;
+pub use role::*;
+pub use schedule::*;
+pub use stats::*;
 pub use event::*;
 pub use storage::*;
+pub use timelock::*;
+pub use token_config::*;
 pub use token_receiver::*;
+pub use upgrade::*;
 pub use utils::*;
+pub use ve::*;
 pub use view::*;
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -25,20 +49,58 @@ pub use view::*;
 enum StorageKey {
     Accounts,
     WhiteList,
+    Lostfound,
+    TokenStats,
+    Roles,
+    TokenPausedOperations,
+    AdminActions,
+    StagedCode,
+    TokenConfigs,
+    VeSupplies,
+    UnlockBalancesItem { token_id: String },
+    TokenCheckpoints,
+    TokenCheckpointsItem { token_id: String },
+    RewardPools,
+    LockOwners,
+    LockApprovals,
+    RewardPoolUnlockIndexes { token_id: String },
 }
 
 #[near(serializers = [borsh])]
 pub struct ContractData {
     owner_id: AccountId,
+    pending_owner_id: Option<AccountId>,
     accounts: UnorderedMap<AccountId, VAccount>,
     token_white_list: UnorderedSet<AccountId>,
     burn_account_id: Option<AccountId>,
+    next_lock_id: LockId,
+    auto_register: bool,
+    storage_sponsor_balance: NearToken,
+    lostfound: UnorderedMap<AccountId, HashMap<String, LostfoundInfo>>,
+    token_stats: UnorderedMap<String, TokenStats>,
+    roles: UnorderedMap<AccountId, Vec<Role>>,
+    paused_operations: Vec<Operation>,
+    token_paused_operations: UnorderedMap<String, Vec<Operation>>,
+    admin_action_delay_sec: u32,
+    next_admin_action_id: AdminActionId,
+    admin_actions: UnorderedMap<AdminActionId, QueuedAdminAction>,
+    staged_upgrade: Option<StagedUpgrade>,
+    token_configs: UnorderedMap<String, TokenConfig>,
+    ve_supplies: UnorderedMap<String, VeSupply>,
+    token_checkpoints: UnorderedMap<String, Vector<Checkpoint>>,
+    reward_pools: UnorderedMap<String, RewardPool>,
+    lock_owners: UnorderedMap<LockId, AccountId>,
+    next_approval_id: u64,
+    lock_approvals: UnorderedMap<LockId, HashMap<AccountId, u64>>,
+    legacy_account_num: u64,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[near(serializers = [borsh])]
 pub enum VersionedContractData {
     V1000(ContractDataV1000),
-    V1001(ContractData),
+    V1001(ContractDataV1001),
+    V1002(ContractData),
 }
 
 #[derive(PanicOnDefault)]
@@ -52,45 +114,106 @@ impl Contract {
     #[init]
     pub fn new(owner_id: AccountId) -> Self {
         Self {
-            data: VersionedContractData::V1001(ContractData {
+            data: VersionedContractData::V1002(ContractData {
                 owner_id,
+                pending_owner_id: None,
                 accounts: UnorderedMap::new(StorageKey::Accounts),
                 token_white_list: UnorderedSet::new(StorageKey::WhiteList),
-                burn_account_id: None
+                burn_account_id: None,
+                next_lock_id: 0,
+                auto_register: false,
+                storage_sponsor_balance: NearToken::from_yoctonear(0),
+                lostfound: UnorderedMap::new(StorageKey::Lostfound),
+                token_stats: UnorderedMap::new(StorageKey::TokenStats),
+                roles: UnorderedMap::new(StorageKey::Roles),
+                paused_operations: vec![],
+                token_paused_operations: UnorderedMap::new(StorageKey::TokenPausedOperations),
+                admin_action_delay_sec: DEFAULT_ADMIN_ACTION_DELAY_SEC,
+                next_admin_action_id: 0,
+                admin_actions: UnorderedMap::new(StorageKey::AdminActions),
+                staged_upgrade: None,
+                token_configs: UnorderedMap::new(StorageKey::TokenConfigs),
+                ve_supplies: UnorderedMap::new(StorageKey::VeSupplies),
+                token_checkpoints: UnorderedMap::new(StorageKey::TokenCheckpoints),
+                reward_pools: UnorderedMap::new(StorageKey::RewardPools),
+                lock_owners: UnorderedMap::new(StorageKey::LockOwners),
+                next_approval_id: 0,
+                lock_approvals: UnorderedMap::new(StorageKey::LockApprovals),
+                legacy_account_num: 0,
             }),
         }
     }
 
     #[payable]
-    pub fn set_owner_id(&mut self, owner_id: AccountId) {
+    pub fn cancel_owner_proposal(&mut self) {
         assert_one_yocto();
         self.assert_owner();
-        self.data_mut().owner_id = owner_id;
+        let pending_owner_id = self.data_mut().pending_owner_id.take().expect("No pending owner");
+        Event::OwnerProposalCancelled {
+            owner_id: &self.data().owner_id,
+            pending_owner_id: &pending_owner_id,
+        }
+        .emit();
     }
 
     #[payable]
-    pub fn set_burn_account_id(&mut self, burn_account_id: AccountId) {
+    pub fn accept_ownership(&mut self) {
         assert_one_yocto();
-        self.assert_owner();
-        self.data_mut().burn_account_id = Some(burn_account_id);
+        let new_owner_id = env::predecessor_account_id();
+        require!(
+            self.data().pending_owner_id.as_ref() == Some(&new_owner_id),
+            "NOT ALLOWED"
+        );
+        let data = self.data_mut();
+        data.pending_owner_id = None;
+        let old_owner_id = std::mem::replace(&mut data.owner_id, new_owner_id.clone());
+        Event::OwnershipTransferred {
+            old_owner_id: &old_owner_id,
+            new_owner_id: &new_owner_id,
+        }
+        .emit();
     }
 
+    /// When enabled, an unregistered account locking for itself is registered with its
+    /// storage paid out of the sponsorship pool, as long as the pool can afford it.
+    /// Locks for unregistered beneficiaries are still refunded.
     #[payable]
-    pub fn extend_token_white_list(&mut self, token_ids: Vec<AccountId>) {
+    pub fn set_auto_register(&mut self, auto_register: bool) {
         assert_one_yocto();
         self.assert_owner();
-        for token_id in token_ids {
-            self.data_mut().token_white_list.insert(&token_id);
-        }
+        self.data_mut().auto_register = auto_register;
     }
 
     #[payable]
-    pub fn remove_token_white_list(&mut self, token_ids: Vec<AccountId>) {
+    pub fn deposit_storage_sponsor(&mut self) {
+        self.assert_owner();
+        let amount = env::attached_deposit();
+        require!(!amount.is_zero(), "Invalid amount");
+        let data = self.data_mut();
+        data.storage_sponsor_balance = data.storage_sponsor_balance.saturating_add(amount);
+        Event::StorageSponsorDeposit { amount: &amount }.emit();
+    }
+
+    #[payable]
+    pub fn withdraw_storage_sponsor(&mut self, amount: Option<NearToken>) -> Promise {
         assert_one_yocto();
         self.assert_owner();
+        let data = self.data_mut();
+        let amount = amount.unwrap_or(data.storage_sponsor_balance);
+        data.storage_sponsor_balance = data
+            .storage_sponsor_balance
+            .checked_sub(amount)
+            .expect("Storage sponsor balance not enough");
+        Event::StorageSponsorWithdraw { amount: &amount }.emit();
+        Promise::new(env::predecessor_account_id()).transfer(amount)
+    }
+
+    #[payable]
+    pub fn extend_token_white_list(&mut self, token_ids: Vec<AccountId>) {
+        assert_one_yocto();
+        self.assert_role(Role::WhitelistManager);
         for token_id in token_ids {
-            let is_success = self.data_mut().token_white_list.remove(&token_id);
-            assert!(is_success, "Invalid token id");
+            self.data_mut().token_white_list.insert(&token_id);
         }
     }
 }
@@ -99,7 +222,7 @@ impl Contract {
     #[allow(unreachable_patterns)]
     fn data(&self) -> &ContractData {
         match &self.data {
-            VersionedContractData::V1001(data) => data,
+            VersionedContractData::V1002(data) => data,
             _ => unimplemented!(),
         }
     }
@@ -107,11 +230,33 @@ impl Contract {
     #[allow(unreachable_patterns)]
     fn data_mut(&mut self) -> &mut ContractData {
         match &mut self.data {
-            VersionedContractData::V1001(data) => data,
+            VersionedContractData::V1002(data) => data,
             _ => unimplemented!(),
         }
     }
 
+    /// First step of an ownership transfer. Nothing changes until `new_owner_id`
+    /// calls `accept_ownership`.
+    pub fn internal_propose_owner(&mut self, new_owner_id: AccountId) {
+        self.data_mut().pending_owner_id = Some(new_owner_id.clone());
+        Event::OwnerProposed {
+            owner_id: &self.data().owner_id,
+            pending_owner_id: &new_owner_id,
+        }
+        .emit();
+    }
+
+    pub fn internal_set_burn_account_id(&mut self, burn_account_id: AccountId) {
+        self.data_mut().burn_account_id = Some(burn_account_id);
+    }
+
+    pub fn internal_remove_token_white_list(&mut self, token_ids: Vec<AccountId>) {
+        for token_id in token_ids {
+            let is_success = self.data_mut().token_white_list.remove(&token_id);
+            assert!(is_success, "Invalid token id");
+        }
+    }
+
     pub fn assert_owner(&self) {
         require!(
             env::predecessor_account_id() == self.data().owner_id,
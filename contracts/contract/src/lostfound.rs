@@ -0,0 +1,125 @@
+use crate::*;
+
+/// Lost-and-found entries older than this can be swept by the owner.
+pub const LOSTFOUND_SWEEP_TIMEOUT_SEC: u32 = 365 * 24 * 3600;
+
+/// Tokens whose transfer out failed after their owner had unregistered.
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct LostfoundInfo {
+    pub amount: U128,
+    pub update_time_sec: u32,
+}
+
+impl Contract {
+    pub fn internal_add_lostfound(&mut self, account_id: &AccountId, token_id: &str, amount: U128) {
+        let mut lostfound = self.data().lostfound.get(account_id).unwrap_or_default();
+        let lostfound_info = lostfound
+            .entry(token_id.to_string())
+            .or_insert(LostfoundInfo {
+                amount: U128(0),
+                update_time_sec: 0,
+            });
+        lostfound_info.amount = U128(lostfound_info.amount.0 + amount.0);
+        lostfound_info.update_time_sec = nano_to_sec(env::block_timestamp());
+        self.data_mut().lostfound.insert(account_id, &lostfound);
+        self.internal_update_token_stats(&token_id.to_string(), |token_stats| {
+            token_stats.total_lostfound = U128(token_stats.total_lostfound.0 + amount.0);
+        });
+    }
+
+    pub fn internal_remove_lostfound(&mut self, account_id: &AccountId, token_id: &str) -> LostfoundInfo {
+        let mut lostfound = self.data().lostfound.get(account_id).expect("No lostfound");
+        let lostfound_info = lostfound.remove(token_id).expect("No lostfound");
+        if lostfound.is_empty() {
+            self.data_mut().lostfound.remove(account_id);
+        } else {
+            self.data_mut().lostfound.insert(account_id, &lostfound);
+        }
+        self.internal_update_token_stats(&token_id.to_string(), |token_stats| {
+            token_stats.total_lostfound = U128(token_stats.total_lostfound.0.saturating_sub(lostfound_info.amount.0));
+        });
+        lostfound_info
+    }
+
+    fn internal_transfer_lostfound(&mut self, account_id: &AccountId, token_id: String, receiver_id: &AccountId, amount: U128) {
+        self.internal_add_pending_transfer(&token_id, amount);
+        token_transfer(&token_id, receiver_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                .after_lostfound_transfer(account_id.clone(), token_id, receiver_id.clone(), amount),
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    /// Sends the caller's lost-and-found balance of `token_id` to the caller. Make sure
+    /// the caller can receive the token again, e.g. by registering on the token contract.
+    #[payable]
+    pub fn claim_lostfound(&mut self, token_id: String) {
+        assert_one_yocto();
+        self.assert_running(Some(&token_id), Operation::Withdraw);
+        let account_id = env::predecessor_account_id();
+        let lostfound_info = self.internal_remove_lostfound(&account_id, &token_id);
+        self.internal_transfer_lostfound(&account_id, token_id.clone(), &account_id, lostfound_info.amount);
+        Event::LostfoundClaimStarted {
+            account_id: &account_id,
+            token_id: &token_id,
+            amount: &lostfound_info.amount,
+        }
+        .emit();
+    }
+
+    /// Moves a lost-and-found entry that nobody claimed for `LOSTFOUND_SWEEP_TIMEOUT_SEC`
+    /// to `receiver_id`.
+    #[payable]
+    pub fn sweep_lostfound(&mut self, account_id: AccountId, token_id: String, receiver_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let lostfound_info = self.internal_remove_lostfound(&account_id, &token_id);
+        require!(
+            lostfound_info.update_time_sec + LOSTFOUND_SWEEP_TIMEOUT_SEC <= nano_to_sec(env::block_timestamp()),
+            "Lostfound not expired"
+        );
+        self.internal_transfer_lostfound(&account_id, token_id.clone(), &receiver_id, lostfound_info.amount);
+        Event::LostfoundSwept {
+            account_id: &account_id,
+            receiver_id: &receiver_id,
+            token_id: &token_id,
+            amount: &lostfound_info.amount,
+        }
+        .emit();
+    }
+
+    #[private]
+    pub fn after_lostfound_transfer(
+        &mut self,
+        account_id: AccountId,
+        token_id: String,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&token_id, amount);
+        if promise_success {
+            Event::LostfoundTransferSucceeded {
+                account_id: &account_id,
+                receiver_id: &receiver_id,
+                token_id: &token_id,
+                amount: &amount,
+            }
+            .emit();
+        } else {
+            self.internal_add_lostfound(&account_id, &token_id, amount);
+            Event::LostfoundTransferFailed {
+                account_id: &account_id,
+                receiver_id: &receiver_id,
+                token_id: &token_id,
+                amount: &amount,
+            }
+            .emit();
+        }
+        promise_success
+    }
+}
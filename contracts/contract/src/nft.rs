@@ -0,0 +1,411 @@
+use crate::*;
+
+use near_contract_standards::non_fungible_token::{
+    approval::ext_nft_approval_receiver,
+    core::NonFungibleTokenCore,
+    enumeration::NonFungibleTokenEnumeration,
+    events::{NftBurn, NftMint, NftTransfer},
+    metadata::{NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC},
+    refund_approved_account_ids, refund_approved_account_ids_iter, refund_deposit, bytes_for_approved_account_id,
+    NonFungibleTokenApproval, NonFungibleTokenResolver, Token, TokenId,
+};
+
+// Every lock position is also an NFT whose token id is the lock id. Its owner holds
+// the withdraw rights, so transferring the NFT transfers the lock, which requires the
+// locked token to be transferable, see `transfer_lock`.
+fn parse_lock_id(token_id: &TokenId) -> Option<LockId> {
+    token_id.parse().ok()
+}
+
+/// Refunds the storage of approvals that were dropped to `owner_id`, who paid for them.
+pub(crate) fn refund_lock_approvals(owner_id: AccountId, approved_account_ids: Option<HashMap<AccountId, u64>>) {
+    if let Some(approved_account_ids) = approved_account_ids.filter(|ids| !ids.is_empty()) {
+        refund_approved_account_ids(owner_id, &approved_account_ids);
+    }
+}
+
+fn lock_token(owner_id: AccountId, lock_id: LockId, token_id: &str, lock_info: &LockInfo, approved_account_ids: HashMap<AccountId, u64>) -> Token {
+    Token {
+        token_id: lock_id.to_string(),
+        owner_id,
+        metadata: Some(TokenMetadata {
+            title: Some(format!("Lock #{}", lock_id)),
+            description: Some(format!(
+                "{} of {} locked until {}",
+                lock_info.locked_balance.0, token_id, lock_info.unlock_time_sec
+            )),
+            extra: Some(
+                json!({
+                    "token_id": token_id,
+                    "amount": lock_info.locked_balance,
+                    "unlock_time_sec": lock_info.unlock_time_sec,
+                })
+                .to_string(),
+            ),
+            ..Default::default()
+        }),
+        approved_account_ids: Some(approved_account_ids),
+    }
+}
+
+impl Contract {
+    /// Keeps `lock_owners` in line with the locks `account_id` holds now, minting the
+    /// NFTs of locks it opened and burning those of locks it closed. Locks handed over
+    /// by `internal_move_lock_owner` are already accounted for. Returns the burnt ids.
+    pub fn internal_update_lock_owners(&mut self, account_id: &AccountId, prev: &[LockId], current: &[LockId]) -> Vec<LockId> {
+        let mut minted = vec![];
+        for lock_id in current.iter().filter(|lock_id| !prev.contains(lock_id)) {
+            if self.data().lock_owners.get(lock_id).is_none() {
+                self.data_mut().lock_owners.insert(lock_id, account_id);
+                minted.push(lock_id.to_string());
+            }
+        }
+        let mut burnt = vec![];
+        for lock_id in prev.iter().filter(|lock_id| !current.contains(lock_id)) {
+            if self.data().lock_owners.get(lock_id).as_ref() == Some(account_id) {
+                self.data_mut().lock_owners.remove(lock_id);
+                burnt.push(*lock_id);
+            }
+        }
+        if !minted.is_empty() {
+            NftMint {
+                owner_id: account_id,
+                token_ids: &minted.iter().map(|token_id| token_id.as_str()).collect::<Vec<_>>(),
+                memo: None,
+            }
+            .emit();
+        }
+        if !burnt.is_empty() {
+            let token_ids: Vec<String> = burnt.iter().map(|lock_id| lock_id.to_string()).collect();
+            NftBurn {
+                owner_id: account_id,
+                token_ids: &token_ids.iter().map(|token_id| token_id.as_str()).collect::<Vec<_>>(),
+                authorized_id: None,
+                memo: None,
+            }
+            .emit();
+        }
+        burnt
+    }
+
+    /// Drops the approvals of burnt locks and refunds their storage to `owner_id`.
+    pub fn internal_clear_lock_approvals(&mut self, owner_id: &AccountId, lock_ids: &[LockId]) {
+        for lock_id in lock_ids {
+            if let Some(approved_account_ids) = self.data_mut().lock_approvals.remove(lock_id) {
+                refund_approved_account_ids(owner_id.clone(), &approved_account_ids);
+            }
+        }
+    }
+
+    /// Records `receiver` as the owner of `lock_id` taken over from `sender`, moving the
+    /// storage charge of the entry along. Clears and returns the lock's approvals.
+    pub fn internal_move_lock_owner(&mut self, sender: &mut Account, receiver: &mut Account, lock_id: LockId) -> HashMap<AccountId, u64> {
+        let initial_storage_usage = env::storage_usage();
+        self.data_mut().lock_owners.remove(&lock_id);
+        sender.storage_usage = sender
+            .storage_usage
+            .saturating_sub(initial_storage_usage.saturating_sub(env::storage_usage()));
+        let initial_storage_usage = env::storage_usage();
+        self.data_mut().lock_owners.insert(&lock_id, &receiver.account_id);
+        receiver.storage_usage += env::storage_usage().saturating_sub(initial_storage_usage);
+        self.data_mut().lock_approvals.remove(&lock_id).unwrap_or_default()
+    }
+
+    /// Legacy accounts have no `lock_owners` entries until they are rewritten, so their
+    /// locks are left out of the NFT views rather than listed but not found.
+    fn internal_get_nft_owner_account(&self, account_id: &AccountId) -> Option<Account> {
+        match self.data().accounts.get(account_id)? {
+            VAccount::Current(account) => Some(account),
+            VAccount::V0(_) => None,
+        }
+    }
+
+    pub fn internal_get_lock_token(&self, lock_id: LockId) -> Option<Token> {
+        let owner_id = self.data().lock_owners.get(&lock_id)?;
+        let account = self.internal_get_account(&owner_id)?;
+        let (token_id, lock_info) = account.find_lock(lock_id)?;
+        let approved_account_ids = self.data().lock_approvals.get(&lock_id).unwrap_or_default();
+        Some(lock_token(owner_id, lock_id, token_id, lock_info, approved_account_ids))
+    }
+
+    /// Transfers the whole lock behind `token_id` on behalf of `sender_id`, who must be
+    /// its owner or approved. Returns the previous owner and the approvals the lock had.
+    fn internal_nft_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) -> (AccountId, HashMap<AccountId, u64>) {
+        let lock_id = parse_lock_id(token_id).expect("Token not found");
+        let owner_id = self.data().lock_owners.get(&lock_id).expect("Token not found");
+        let authorized_id = if *sender_id != owner_id {
+            let approved_account_ids = self.data().lock_approvals.get(&lock_id).unwrap_or_default();
+            let actual_approval_id = approved_account_ids.get(sender_id).expect("Sender not approved");
+            require!(
+                approval_id.map_or(true, |approval_id| approval_id == *actual_approval_id),
+                "Invalid approval_id"
+            );
+            Some(sender_id)
+        } else {
+            None
+        };
+        let lock_token_id = self
+            .internal_unwrap_account(&owner_id)
+            .find_lock(lock_id)
+            .map(|(lock_token_id, _)| lock_token_id.clone())
+            .expect("Token not found");
+        let (_, approved_account_ids) = self.internal_transfer_lock(&owner_id, receiver_id, &lock_token_id, lock_id, None);
+        NftTransfer {
+            old_owner_id: &owner_id,
+            new_owner_id: receiver_id,
+            token_ids: &[token_id],
+            authorized_id: authorized_id.map(|account_id| account_id.as_ref()),
+            memo: memo.as_deref(),
+        }
+        .emit();
+        (owner_id, approved_account_ids)
+    }
+
+    fn assert_lock_owner(&self, lock_id: LockId) -> AccountId {
+        let owner_id = self.data().lock_owners.get(&lock_id).expect("Token not found");
+        require!(env::predecessor_account_id() == owner_id, "Predecessor must be token owner");
+        owner_id
+    }
+}
+
+#[near]
+impl NonFungibleTokenCore for Contract {
+    #[payable]
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        let (previous_owner_id, approved_account_ids) =
+            self.internal_nft_transfer(&env::predecessor_account_id(), &receiver_id, &token_id, approval_id, memo);
+        refund_lock_approvals(previous_owner_id, Some(approved_account_ids));
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (previous_owner_id, approved_account_ids) =
+            self.internal_nft_transfer(&sender_id, &receiver_id, &token_id, approval_id, memo);
+        ext_nft_receiver::ext(receiver_id.clone())
+            .with_static_gas(GAS_FOR_NFT_ON_TRANSFER)
+            .nft_on_transfer(sender_id, previous_owner_id.clone(), token_id.clone(), msg)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_NFT_RESOLVE_TRANSFER)
+                    .nft_resolve_transfer(previous_owner_id, receiver_id, token_id, Some(approved_account_ids)),
+            )
+            .into()
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.internal_get_lock_token(parse_lock_id(&token_id)?)
+    }
+}
+
+#[near]
+impl NonFungibleTokenResolver for Contract {
+    /// Hands the lock back to `previous_owner_id` when the receiver asked for it or
+    /// failed, unless the receiver no longer holds it or the previous owner is gone.
+    /// Approvals come back with the lock, otherwise their storage is refunded.
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        let must_revert = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<bool>(&value).unwrap_or(true),
+            PromiseResult::Failed => true,
+        };
+        if !must_revert {
+            refund_lock_approvals(previous_owner_id, approved_account_ids);
+            return true;
+        }
+        let lock_id = parse_lock_id(&token_id).unwrap();
+        if self.data().lock_owners.get(&lock_id).as_ref() != Some(&receiver_id) {
+            refund_lock_approvals(previous_owner_id, approved_account_ids);
+            return true;
+        }
+        let (Some(mut receiver), Some(mut previous_owner)) = (
+            self.internal_get_account(&receiver_id),
+            self.internal_get_account(&previous_owner_id),
+        ) else {
+            refund_lock_approvals(previous_owner_id, approved_account_ids);
+            return true;
+        };
+        let Some(lock_token_id) = receiver.find_lock(lock_id).map(|(lock_token_id, _)| lock_token_id.clone()) else {
+            refund_lock_approvals(previous_owner_id, approved_account_ids);
+            return true;
+        };
+        let lock_info = receiver.remove_lock(&lock_token_id, lock_id).unwrap();
+        let amount = lock_info.locked_balance;
+        previous_owner.insert_lock(&lock_token_id, lock_id, lock_info);
+        let receiver_approvals = self.internal_move_lock_owner(&mut receiver, &mut previous_owner, lock_id);
+        refund_lock_approvals(receiver_id.clone(), Some(receiver_approvals));
+        self.internal_force_set_account(&previous_owner_id, previous_owner);
+        self.internal_force_set_account(&receiver_id, receiver);
+        if let Some(approved_account_ids) = approved_account_ids.filter(|ids| !ids.is_empty()) {
+            self.data_mut().lock_approvals.insert(&lock_id, &approved_account_ids);
+        }
+        Event::LockTransferred {
+            account_id: &receiver_id,
+            receiver_id: &previous_owner_id,
+            token_id: &lock_token_id,
+            lock_id,
+            receiver_lock_id: lock_id,
+            amount: &amount,
+        }
+        .emit();
+        NftTransfer {
+            old_owner_id: &receiver_id,
+            new_owner_id: &previous_owner_id,
+            token_ids: &[&token_id],
+            authorized_id: None,
+            memo: None,
+        }
+        .emit();
+        false
+    }
+}
+
+#[near]
+impl NonFungibleTokenApproval for Contract {
+    /// The attached deposit pays for storing the approval; the rest is refunded.
+    #[payable]
+    fn nft_approve(&mut self, token_id: TokenId, account_id: AccountId, msg: Option<String>) -> Option<Promise> {
+        require!(
+            env::attached_deposit() >= NearToken::from_yoctonear(1),
+            "Requires attached deposit of at least 1 yoctoNEAR"
+        );
+        let lock_id = parse_lock_id(&token_id).expect("Token not found");
+        let owner_id = self.assert_lock_owner(lock_id);
+        let data = self.data_mut();
+        let approval_id = data.next_approval_id;
+        data.next_approval_id += 1;
+        let mut approved_account_ids = data.lock_approvals.get(&lock_id).unwrap_or_default();
+        let is_new = approved_account_ids.insert(account_id.clone(), approval_id).is_none();
+        data.lock_approvals.insert(&lock_id, &approved_account_ids);
+        refund_deposit(if is_new { bytes_for_approved_account_id(&account_id) } else { 0 });
+        msg.map(|msg| {
+            ext_nft_approval_receiver::ext(account_id)
+                .with_static_gas(GAS_FOR_NFT_ON_APPROVE)
+                .nft_on_approve(token_id, owner_id, approval_id, msg)
+        })
+    }
+
+    #[payable]
+    fn nft_revoke(&mut self, token_id: TokenId, account_id: AccountId) {
+        assert_one_yocto();
+        let lock_id = parse_lock_id(&token_id).expect("Token not found");
+        let owner_id = self.assert_lock_owner(lock_id);
+        let mut approved_account_ids = self.data().lock_approvals.get(&lock_id).unwrap_or_default();
+        if approved_account_ids.remove(&account_id).is_some() {
+            if approved_account_ids.is_empty() {
+                self.data_mut().lock_approvals.remove(&lock_id);
+            } else {
+                self.data_mut().lock_approvals.insert(&lock_id, &approved_account_ids);
+            }
+            refund_approved_account_ids_iter(owner_id, [account_id].iter());
+        }
+    }
+
+    #[payable]
+    fn nft_revoke_all(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        let lock_id = parse_lock_id(&token_id).expect("Token not found");
+        let owner_id = self.assert_lock_owner(lock_id);
+        self.internal_clear_lock_approvals(&owner_id, &[lock_id]);
+    }
+
+    fn nft_is_approved(&self, token_id: TokenId, approved_account_id: AccountId, approval_id: Option<u64>) -> bool {
+        let Some(lock_id) = parse_lock_id(&token_id) else {
+            return false;
+        };
+        self.data()
+            .lock_approvals
+            .get(&lock_id)
+            .and_then(|approved_account_ids| approved_account_ids.get(&approved_account_id).copied())
+            .map_or(false, |actual_approval_id| {
+                approval_id.map_or(true, |approval_id| approval_id == actual_approval_id)
+            })
+    }
+}
+
+#[near]
+impl NonFungibleTokenEnumeration for Contract {
+    fn nft_total_supply(&self) -> U128 {
+        U128(self.data().lock_owners.len() as u128)
+    }
+
+    fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let from_index = from_index.map_or(0, |from_index| from_index.0 as u64);
+        let limit = limit.unwrap_or(self.data().lock_owners.len());
+        self.data()
+            .lock_owners
+            .keys_as_vector()
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .filter_map(|lock_id| self.internal_get_lock_token(lock_id))
+            .collect()
+    }
+
+    fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        U128(self.internal_get_nft_owner_account(&account_id).map_or(0, |account| account.lock_num() as u128))
+    }
+
+    /// Same positions as `get_account`, in lock id order.
+    fn nft_tokens_for_owner(&self, account_id: AccountId, from_index: Option<U128>, limit: Option<u64>) -> Vec<Token> {
+        let Some(account) = self.internal_get_nft_owner_account(&account_id) else {
+            return vec![];
+        };
+        let from_index = from_index.map_or(0, |from_index| from_index.0 as usize);
+        let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+        account
+            .lock_ids()
+            .into_iter()
+            .skip(from_index)
+            .take(limit)
+            .map(|lock_id| {
+                let (token_id, lock_info) = account.find_lock(lock_id).unwrap();
+                let approved_account_ids = self.data().lock_approvals.get(&lock_id).unwrap_or_default();
+                lock_token(account_id.clone(), lock_id, token_id, lock_info, approved_account_ids)
+            })
+            .collect()
+    }
+}
+
+#[near]
+impl NonFungibleTokenMetadataProvider for Contract {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Token Locker Positions".to_string(),
+            symbol: "LOCK".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::*;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum Operation {
+    Lock,
+    Withdraw,
+    Burn,
+    /// Storage deposit, withdraw and unregister. Only pausable contract-wide.
+    Storage,
+    /// Handing locks to other accounts.
+    Transfer,
+}
+
+impl Contract {
+    /// Whether `operation` is allowed contract-wide and, when given, for `token_id`.
+    pub fn is_running(&self, token_id: Option<&String>, operation: Operation) -> bool {
+        !self.data().paused_operations.contains(&operation)
+            && token_id.map_or(true, |token_id| {
+                !self
+                    .data()
+                    .token_paused_operations
+                    .get(token_id)
+                    .unwrap_or_default()
+                    .contains(&operation)
+            })
+    }
+
+    pub fn assert_running(&self, token_id: Option<&String>, operation: Operation) {
+        require!(self.is_running(token_id, operation), "Operation paused");
+    }
+
+    pub fn internal_get_paused_operations(&self, token_id: Option<&String>) -> Vec<Operation> {
+        match token_id {
+            Some(token_id) => self.data().token_paused_operations.get(token_id).unwrap_or_default(),
+            None => self.data().paused_operations.clone(),
+        }
+    }
+
+    fn internal_set_paused_operations(&mut self, token_id: Option<&String>, operations: Vec<Operation>) {
+        match token_id {
+            Some(token_id) if operations.is_empty() => {
+                self.data_mut().token_paused_operations.remove(token_id);
+            }
+            Some(token_id) => {
+                self.data_mut().token_paused_operations.insert(token_id, &operations);
+            }
+            None => self.data_mut().paused_operations = operations,
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    /// Stops `operations` contract-wide, or only for `token_id` when given.
+    #[payable]
+    pub fn pause(&mut self, token_id: Option<String>, operations: Vec<Operation>) {
+        assert_one_yocto();
+        self.assert_role(Role::PauseGuardian);
+        require!(
+            token_id.is_none() || !operations.contains(&Operation::Storage),
+            "Storage can only be paused contract-wide"
+        );
+        let mut paused_operations = self.internal_get_paused_operations(token_id.as_ref());
+        for operation in operations.iter() {
+            if !paused_operations.contains(operation) {
+                paused_operations.push(*operation);
+            }
+        }
+        self.internal_set_paused_operations(token_id.as_ref(), paused_operations);
+        Event::Paused {
+            by: &env::predecessor_account_id(),
+            token_id: token_id.as_ref(),
+            operations: &operations,
+        }
+        .emit();
+    }
+
+    /// Lifts a pause set by `pause`. Only callable by the owner.
+    #[payable]
+    pub fn resume(&mut self, token_id: Option<String>, operations: Vec<Operation>) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut paused_operations = self.internal_get_paused_operations(token_id.as_ref());
+        paused_operations.retain(|operation| !operations.contains(operation));
+        self.internal_set_paused_operations(token_id.as_ref(), paused_operations);
+        Event::Resumed {
+            by: &env::predecessor_account_id(),
+            token_id: token_id.as_ref(),
+            operations: &operations,
+        }
+        .emit();
+    }
+}
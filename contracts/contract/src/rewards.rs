@@ -0,0 +1,546 @@
+use crate::*;
+
+pub const ACC_REWARD_PER_SHARE_PRECISION: u128 = 10u128.pow(24);
+
+/// What a stream paid per share so far, scaled by `ACC_REWARD_PER_SHARE_PRECISION`. In a
+/// ve pool a lock of `amount` unlocking at `unlock_time_sec` earns `amount * (unlock_time_sec
+/// * acc_reward_per_share - acc_reward_time_per_share)` between two indexes.
+#[near(serializers = [borsh])]
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct RewardIndex {
+    pub acc_reward_per_share: U256,
+    /// Sum of each increase of `acc_reward_per_share` times the time the ve weights were
+    /// taken at. Stays zero in amount pools.
+    pub acc_reward_time_per_share: U256,
+}
+
+/// One reward token streamed to the lockers of a token.
+#[near(serializers = [borsh])]
+pub struct RewardStream {
+    pub reward_per_sec: u128,
+    pub period_finish_sec: u32,
+    pub last_update_sec: u32,
+    pub index: RewardIndex,
+}
+
+impl RewardStream {
+    /// Distributes what was streamed since the last update over `total_shares`. While
+    /// nobody holds shares nothing is distributed, and the backlog goes to the first
+    /// shares to show up.
+    fn update(&mut self, total_shares: u128, current_time_sec: u32) {
+        let time_sec = std::cmp::min(current_time_sec, self.period_finish_sec);
+        if time_sec <= self.last_update_sec || total_shares == 0 {
+            return;
+        }
+        self.index.acc_reward_per_share += self.reward_per_share(time_sec, U256::from(total_shares));
+        self.last_update_sec = time_sec;
+    }
+
+    /// Same as `update` over the ve weight of `supply`, taken halfway through the period.
+    /// Nothing in `supply` may unlock before `current_time_sec`.
+    fn update_ve(&mut self, supply: &VeSupply, current_time_sec: u32) {
+        let time_sec = std::cmp::min(current_time_sec, self.period_finish_sec);
+        if time_sec <= self.last_update_sec {
+            return;
+        }
+        let weight_time_sec = self.last_update_sec + (time_sec - self.last_update_sec) / 2;
+        let weight = supply.weight_at(weight_time_sec);
+        if weight.is_zero() {
+            return;
+        }
+        let reward_per_share = self.reward_per_share(time_sec, weight);
+        self.index.acc_reward_per_share += reward_per_share;
+        self.index.acc_reward_time_per_share += reward_per_share * U256::from(weight_time_sec);
+        self.last_update_sec = time_sec;
+    }
+
+    fn reward_per_share(&self, time_sec: u32, total_shares: U256) -> U256 {
+        U256::from(self.reward_per_sec)
+            * U256::from(time_sec - self.last_update_sec)
+            * U256::from(ACC_REWARD_PER_SHARE_PRECISION)
+            / total_shares
+    }
+}
+
+/// Rewards for the lockers of a token. Shares are the locked amount. In a ve pool, created
+/// while the token had a max lock duration, rewards instead go by ve weight, the amount
+/// times the time left until the epoch-rounded unlock time, as in the token's ve supply.
+#[near(serializers = [borsh])]
+pub struct RewardPool {
+    pub total_shares: u128,
+    pub is_ve: bool,
+    pub start_time_sec: u32,
+    pub streams: HashMap<String, RewardStream>,
+    /// Indexes of a ve pool's streams at each unlock time it passed, where the locks
+    /// unlocking then stopped earning. Kept for the accounts that settle later, one per
+    /// epoch at most, so the contract pays for them.
+    pub unlock_indexes: LookupMap<u32, HashMap<String, RewardIndex>>,
+}
+
+impl RewardPool {
+    fn new(lock_token_id: &str, is_ve: bool, total_shares: u128) -> Self {
+        Self {
+            total_shares,
+            is_ve,
+            start_time_sec: nano_to_sec(env::block_timestamp()),
+            streams: HashMap::new(),
+            unlock_indexes: LookupMap::new(StorageKey::RewardPoolUnlockIndexes {
+                token_id: lock_token_id.to_string(),
+            }),
+        }
+    }
+
+    fn update(&mut self, current_time_sec: u32) {
+        for stream in self.streams.values_mut() {
+            stream.update(self.total_shares, current_time_sec);
+        }
+    }
+
+    /// Brings a ve pool and `supply` up to `current_time_sec` together, retiring the unlocks
+    /// due on the way. Returns the indexes at each of them, not saved yet.
+    pub fn update_ve(&mut self, supply: &mut VeSupply, current_time_sec: u32) -> Vec<(u32, HashMap<String, RewardIndex>)> {
+        let mut unlock_indexes = vec![];
+        supply.advance(current_time_sec, |supply, unlock_time_sec| {
+            for stream in self.streams.values_mut() {
+                stream.update_ve(supply, unlock_time_sec);
+            }
+            unlock_indexes.push((unlock_time_sec, self.indexes()));
+        });
+        for stream in self.streams.values_mut() {
+            stream.update_ve(supply, current_time_sec);
+        }
+        unlock_indexes
+    }
+
+    fn indexes(&self) -> HashMap<String, RewardIndex> {
+        self.streams
+            .iter()
+            .map(|(reward_token_id, stream)| (reward_token_id.clone(), stream.index))
+            .collect()
+    }
+
+    /// Rewards per stream earned in a ve pool by `balances` since `paid` at `paid_time_sec`.
+    /// Each lock earns up to its epoch-rounded unlock time, looking its index there up in
+    /// `unlock_indexes` first when it was not saved yet.
+    fn ve_earned(
+        &self,
+        unlock_indexes: &[(u32, HashMap<String, RewardIndex>)],
+        balances: &HashMap<u32, u128>,
+        paid: &HashMap<String, RewardIndex>,
+        paid_time_sec: u32,
+        current_time_sec: u32,
+    ) -> HashMap<String, u128> {
+        let mut earned: HashMap<String, U256> = HashMap::new();
+        for (unlock_time_sec, balance) in ve_unlock_balances(balances, paid_time_sec) {
+            let end_indexes = if unlock_time_sec <= current_time_sec {
+                unlock_indexes
+                    .iter()
+                    .find(|(time_sec, _)| *time_sec == unlock_time_sec)
+                    .map(|(_, indexes)| indexes.clone())
+                    .or_else(|| self.unlock_indexes.get(&unlock_time_sec))
+                    .unwrap_or_default()
+            } else {
+                self.indexes()
+            };
+            for reward_token_id in self.streams.keys() {
+                let paid = paid.get(reward_token_id).copied().unwrap_or_default();
+                // A stream that only started after the unlock paid nothing to it.
+                let end = end_indexes.get(reward_token_id).copied().unwrap_or(paid);
+                *earned.entry(reward_token_id.clone()).or_default() += U256::from(balance)
+                    * (U256::from(unlock_time_sec) * (end.acc_reward_per_share - paid.acc_reward_per_share)
+                        - (end.acc_reward_time_per_share - paid.acc_reward_time_per_share));
+            }
+        }
+        earned
+            .into_iter()
+            .map(|(reward_token_id, earned)| {
+                (reward_token_id, (earned / U256::from(ACC_REWARD_PER_SHARE_PRECISION)).as_u128())
+            })
+            .collect()
+    }
+}
+
+/// An account's stake in the reward pool of one lock token. Accounts that were not written
+/// since the pool was created have none and hold their locked amount as shares, or their
+/// locks since the pool started in a ve pool.
+#[near(serializers = [borsh])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct AccountRewards {
+    pub shares: u128,
+    pub reward_per_share_paid: HashMap<String, RewardIndex>,
+    pub update_time_sec: u32,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct RewardStreamInfo {
+    pub reward_token_id: String,
+    pub reward_per_sec: U128,
+    pub period_finish_sec: u32,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct RewardPoolInfo {
+    pub total_shares: U128,
+    pub is_ve: bool,
+    pub streams: Vec<RewardStreamInfo>,
+}
+
+impl From<RewardPool> for RewardPoolInfo {
+    fn from(pool: RewardPool) -> Self {
+        Self {
+            total_shares: U128(pool.total_shares),
+            is_ve: pool.is_ve,
+            streams: pool
+                .streams
+                .into_iter()
+                .map(|(reward_token_id, stream)| RewardStreamInfo {
+                    reward_token_id,
+                    reward_per_sec: U128(stream.reward_per_sec),
+                    period_finish_sec: stream.period_finish_sec,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Lock tokens whose reward pools `account` may have a stake in.
+fn reward_lock_token_ids(account: &Account, prev_locked_balances: &HashMap<String, HashMap<u32, u128>>) -> Vec<String> {
+    let mut lock_token_ids: Vec<String> = account.locked_tokens.keys().cloned().collect();
+    for token_id in account.rewards.keys().chain(prev_locked_balances.keys()) {
+        if !lock_token_ids.contains(token_id) {
+            lock_token_ids.push(token_id.clone());
+        }
+    }
+    lock_token_ids
+}
+
+impl Contract {
+    pub fn internal_fund_rewards(
+        &mut self,
+        funder_id: &AccountId,
+        lock_token_id: &String,
+        reward_token_id: &String,
+        amount: U128,
+        duration_sec: u32,
+    ) {
+        require!(duration_sec > 0 && amount.0 > 0, "Invalid reward");
+        self.assert_white_list_token(&parse_token_id(lock_token_id).0);
+        let current_time_sec = nano_to_sec(env::block_timestamp());
+        let mut pool = self.internal_update_reward_pool(lock_token_id).unwrap_or_else(|| {
+            let is_ve = self.internal_get_token_config(lock_token_id).max_lock_duration_sec.is_some();
+            if is_ve {
+                self.internal_checkpoint_ve_supply(lock_token_id);
+            }
+            // Everything locked so far is in the pool from the start.
+            RewardPool::new(lock_token_id, is_ve, self.internal_get_token_stats(lock_token_id).total_locked.0)
+        });
+        let stream = pool.streams.entry(reward_token_id.clone()).or_insert(RewardStream {
+            reward_per_sec: 0,
+            period_finish_sec: current_time_sec,
+            last_update_sec: current_time_sec,
+            index: RewardIndex::default(),
+        });
+        // Whatever was not distributed yet is spread over the new period as well.
+        let leftover = if stream.last_update_sec < stream.period_finish_sec {
+            stream.reward_per_sec * (stream.period_finish_sec - stream.last_update_sec) as u128
+        } else {
+            0
+        };
+        let reward_per_sec = (amount.0 + leftover) / duration_sec as u128;
+        require!(reward_per_sec > 0, "Invalid reward");
+        // Anyone can fund, so a running stream only takes top-ups that keep its rate. A dust
+        // top-up over a long duration would otherwise stretch it out.
+        require!(
+            stream.period_finish_sec <= current_time_sec || reward_per_sec >= stream.reward_per_sec,
+            "Reward rate can not decrease"
+        );
+        stream.reward_per_sec = reward_per_sec;
+        stream.last_update_sec = current_time_sec;
+        stream.period_finish_sec = current_time_sec + duration_sec;
+        let period_finish_sec = stream.period_finish_sec;
+        self.data_mut().reward_pools.insert(lock_token_id, &pool);
+        self.internal_update_token_stats(reward_token_id, |token_stats| {
+            token_stats.total_reward = U128(token_stats.total_reward.0 + amount.0);
+        });
+        Event::RewardsFunded {
+            funder_id,
+            lock_token_id,
+            reward_token_id,
+            amount: &amount,
+            duration_sec,
+            period_finish_sec,
+        }
+        .emit();
+    }
+
+    /// Locked balances of `account_id` as counted in the token stats, i.e. none while it
+    /// is a legacy account.
+    pub fn internal_get_counted_locked_balances(&self, account_id: &AccountId) -> HashMap<String, HashMap<u32, u128>> {
+        match self.data().accounts.get(account_id) {
+            Some(VAccount::Current(account)) => account.locked_balances(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// The reward pool of `lock_token_id` brought up to now. A ve pool is checkpointed along
+    /// with the ve supply, which saves it.
+    fn internal_update_reward_pool(&mut self, lock_token_id: &String) -> Option<RewardPool> {
+        let mut pool = self.data().reward_pools.get(lock_token_id)?;
+        if pool.is_ve {
+            self.internal_checkpoint_ve_supply(lock_token_id);
+            return self.data().reward_pools.get(lock_token_id);
+        }
+        pool.update(nano_to_sec(env::block_timestamp()));
+        Some(pool)
+    }
+
+    /// Moves the rewards `account` earned so far into its unclaimed rewards and resizes
+    /// its shares to its current locks. `prev_locked_balances` are its counted balances
+    /// before this change, which earned since it was last settled. Returns the updated
+    /// pools without saving them.
+    fn internal_accrue_rewards(
+        &self,
+        account: &mut Account,
+        prev_locked_balances: &HashMap<String, HashMap<u32, u128>>,
+        current_time_sec: u32,
+    ) -> Vec<(String, RewardPool)> {
+        let empty = HashMap::new();
+        let mut pools = vec![];
+        for lock_token_id in reward_lock_token_ids(account, prev_locked_balances) {
+            let Some(mut pool) = self.data().reward_pools.get(&lock_token_id) else {
+                continue;
+            };
+            let unlock_indexes = if pool.is_ve {
+                pool.update_ve(&mut self.internal_get_ve_supply(&lock_token_id), current_time_sec)
+            } else {
+                pool.update(current_time_sec);
+                vec![]
+            };
+            let prev_balances = prev_locked_balances.get(&lock_token_id).unwrap_or(&empty);
+            let mut account_rewards = account.rewards.remove(&lock_token_id).unwrap_or_else(|| AccountRewards {
+                shares: prev_balances.values().sum(),
+                reward_per_share_paid: HashMap::new(),
+                update_time_sec: pool.start_time_sec,
+            });
+            let earned = if pool.is_ve {
+                pool.ve_earned(
+                    &unlock_indexes,
+                    prev_balances,
+                    &account_rewards.reward_per_share_paid,
+                    account_rewards.update_time_sec,
+                    current_time_sec,
+                )
+            } else {
+                pool.streams
+                    .iter()
+                    .map(|(reward_token_id, stream)| {
+                        let paid = account_rewards.reward_per_share_paid.get(reward_token_id).copied().unwrap_or_default();
+                        let earned = U256::from(account_rewards.shares)
+                            * (stream.index.acc_reward_per_share - paid.acc_reward_per_share)
+                            / U256::from(ACC_REWARD_PER_SHARE_PRECISION);
+                        (reward_token_id.clone(), earned.as_u128())
+                    })
+                    .collect()
+            };
+            for (reward_token_id, earned) in earned {
+                if earned > 0 {
+                    let unclaimed = account.unclaimed_rewards.entry(reward_token_id).or_insert(U128(0));
+                    unclaimed.0 += earned;
+                }
+            }
+            account_rewards.reward_per_share_paid = pool.indexes();
+            account_rewards.update_time_sec = current_time_sec;
+            let shares = account
+                .locked_tokens
+                .get(&lock_token_id)
+                .map_or(0, |locks| locks.values().map(|lock_info| lock_info.locked_balance.0).sum());
+            pool.total_shares = pool.total_shares - account_rewards.shares + shares;
+            account_rewards.shares = shares;
+            if shares > 0 {
+                account.rewards.insert(lock_token_id.clone(), account_rewards);
+            }
+            pools.push((lock_token_id, pool));
+        }
+        pools
+    }
+
+    pub fn internal_settle_rewards(&mut self, account: &mut Account, prev_locked_balances: &HashMap<String, HashMap<u32, u128>>) {
+        // Ve pools go first, so that the indexes at the unlocks they pass are saved.
+        for lock_token_id in reward_lock_token_ids(account, prev_locked_balances) {
+            if self.data().reward_pools.get(&lock_token_id).is_some_and(|pool| pool.is_ve) {
+                self.internal_checkpoint_ve_supply(&lock_token_id);
+            }
+        }
+        let pools = self.internal_accrue_rewards(account, prev_locked_balances, nano_to_sec(env::block_timestamp()));
+        for (lock_token_id, pool) in pools {
+            self.data_mut().reward_pools.insert(&lock_token_id, &pool);
+        }
+    }
+
+    /// Unclaimed rewards of `account_id` including what accrued since it was last saved.
+    pub fn internal_get_unclaimed_rewards(&self, account_id: &AccountId) -> HashMap<String, U128> {
+        let Some(mut account) = self.internal_get_account(account_id) else {
+            return HashMap::new();
+        };
+        let prev_locked_balances = self.internal_get_counted_locked_balances(account_id);
+        self.internal_accrue_rewards(&mut account, &prev_locked_balances, nano_to_sec(env::block_timestamp()));
+        account.unclaimed_rewards
+    }
+
+    fn internal_credit_rewards(&mut self, account: &mut Account, reward_token_id: &String, amount: U128) {
+        let unclaimed = account.unclaimed_rewards.entry(reward_token_id.clone()).or_insert(U128(0));
+        unclaimed.0 += amount.0;
+        self.internal_update_token_stats(reward_token_id, |token_stats| {
+            token_stats.total_reward = U128(token_stats.total_reward.0 + amount.0);
+        });
+    }
+
+    fn internal_debit_rewards(&mut self, reward_token_id: &String, amount: U128) {
+        self.internal_update_token_stats(reward_token_id, |token_stats| {
+            token_stats.total_reward = U128(token_stats.total_reward.0 - amount.0);
+        });
+    }
+
+    /// Hands the unclaimed rewards of an account that is going away to the lost-and-found.
+    pub fn internal_move_rewards_to_lostfound(&mut self, account_id: &AccountId, account: &mut Account) {
+        for (reward_token_id, amount) in std::mem::take(&mut account.unclaimed_rewards) {
+            self.internal_debit_rewards(&reward_token_id, amount);
+            self.internal_add_lostfound(account_id, &reward_token_id, amount);
+            Event::RewardsLostfound {
+                account_id,
+                reward_token_id: &reward_token_id,
+                amount: &amount,
+            }
+            .emit();
+        }
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn claim_rewards(&mut self, reward_token_id: String) {
+        assert_one_yocto();
+        self.assert_running(Some(&reward_token_id), Operation::Withdraw);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let prev_locked_balances = self.internal_get_counted_locked_balances(&account_id);
+        self.internal_settle_rewards(&mut account, &prev_locked_balances);
+        let amount = account.unclaimed_rewards.remove(&reward_token_id).expect("No rewards");
+        self.internal_force_set_account(&account_id, account);
+        self.internal_debit_rewards(&reward_token_id, amount);
+        self.internal_add_pending_transfer(&reward_token_id, amount);
+        token_transfer(&reward_token_id, &account_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                .after_rewards_transfer(account_id.clone(), reward_token_id.clone(), amount),
+        );
+        Event::RewardsClaimStarted {
+            account_id: &account_id,
+            reward_token_id: &reward_token_id,
+            amount: &amount,
+        }
+        .emit();
+    }
+
+    /// Sends `receiver_id` what a stream of `lock_token_id` has not distributed yet, while
+    /// nobody holds shares in the pool to distribute it to, or ve weight in a ve pool.
+    #[payable]
+    pub fn reclaim_rewards(&mut self, lock_token_id: String, reward_token_id: String, receiver_id: AccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut pool = self.internal_update_reward_pool(&lock_token_id).expect("No reward pool");
+        let has_shares = if pool.is_ve {
+            self.internal_get_ve_supply(&lock_token_id).amount > 0
+        } else {
+            pool.total_shares > 0
+        };
+        require!(!has_shares, "Reward pool has shares");
+        let stream = pool.streams.get_mut(&reward_token_id).expect("No reward stream");
+        let amount = U128(
+            stream.reward_per_sec * stream.period_finish_sec.saturating_sub(stream.last_update_sec) as u128,
+        );
+        require!(amount.0 > 0, "Nothing to reclaim");
+        if pool.is_ve {
+            // Accounts with unlocked locks may still hold a paid index of the stream, so a ve
+            // pool keeps it, stopped where it is.
+            stream.period_finish_sec = stream.last_update_sec;
+        } else {
+            pool.streams.remove(&reward_token_id);
+        }
+        self.data_mut().reward_pools.insert(&lock_token_id, &pool);
+        self.internal_debit_rewards(&reward_token_id, amount);
+        self.internal_add_pending_transfer(&reward_token_id, amount);
+        token_transfer(&reward_token_id, &receiver_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                .after_reclaim_transfer(reward_token_id.clone(), receiver_id.clone(), amount),
+        );
+        Event::RewardsReclaimStarted {
+            lock_token_id: &lock_token_id,
+            reward_token_id: &reward_token_id,
+            receiver_id: &receiver_id,
+            amount: &amount,
+        }
+        .emit();
+    }
+
+    #[private]
+    pub fn after_rewards_transfer(&mut self, account_id: AccountId, reward_token_id: String, amount: U128) -> bool {
+        let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&reward_token_id, amount);
+        if promise_success {
+            Event::RewardsClaimSucceeded {
+                account_id: &account_id,
+                reward_token_id: &reward_token_id,
+                amount: &amount,
+            }
+            .emit();
+        } else if let Some(mut account) = self.internal_get_account(&account_id) {
+            self.internal_credit_rewards(&mut account, &reward_token_id, amount);
+            self.internal_force_set_account(&account_id, account);
+            Event::RewardsClaimFailed {
+                account_id: &account_id,
+                reward_token_id: &reward_token_id,
+                amount: &amount,
+            }
+            .emit();
+        } else {
+            self.internal_add_lostfound(&account_id, &reward_token_id, amount);
+            Event::RewardsLostfound {
+                account_id: &account_id,
+                reward_token_id: &reward_token_id,
+                amount: &amount,
+            }
+            .emit();
+        }
+        promise_success
+    }
+
+    /// A failed reclaim goes to the lost-and-found of `receiver_id`.
+    #[private]
+    pub fn after_reclaim_transfer(&mut self, reward_token_id: String, receiver_id: AccountId, amount: U128) -> bool {
+        let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&reward_token_id, amount);
+        if promise_success {
+            Event::RewardsReclaimSucceeded {
+                reward_token_id: &reward_token_id,
+                receiver_id: &receiver_id,
+                amount: &amount,
+            }
+            .emit();
+        } else {
+            self.internal_add_lostfound(&receiver_id, &reward_token_id, amount);
+            Event::RewardsReclaimFailed {
+                reward_token_id: &reward_token_id,
+                receiver_id: &receiver_id,
+                amount: &amount,
+            }
+            .emit();
+        }
+        promise_success
+    }
+}
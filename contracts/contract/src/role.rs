@@ -0,0 +1,72 @@
+use crate::*;
+
+/// Narrow admin permissions. The owner implicitly holds every role and is the only
+/// one who can grant or revoke them.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum Role {
+    /// Manages the token white list.
+    WhitelistManager,
+    /// Pauses operations. Resuming stays with the owner.
+    PauseGuardian,
+    /// Upgrades the contract code.
+    Upgrader,
+    /// Sets the account burnt tokens are sent to.
+    BurnManager,
+}
+
+impl Contract {
+    pub fn internal_has_role(&self, account_id: &AccountId, role: Role) -> bool {
+        *account_id == self.data().owner_id
+            || self
+                .data()
+                .roles
+                .get(account_id)
+                .map_or(false, |roles| roles.contains(&role))
+    }
+
+    pub fn assert_role(&self, role: Role) {
+        require!(
+            self.internal_has_role(&env::predecessor_account_id(), role),
+            "NOT ALLOWED"
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut roles = self.data().roles.get(&account_id).unwrap_or_default();
+        require!(!roles.contains(&role), "Role already granted");
+        roles.push(role);
+        self.data_mut().roles.insert(&account_id, &roles);
+        Event::RoleGranted {
+            account_id: &account_id,
+            role,
+        }
+        .emit();
+    }
+
+    #[payable]
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        assert_one_yocto();
+        self.assert_owner();
+        let mut roles = self.data().roles.get(&account_id).unwrap_or_default();
+        require!(roles.contains(&role), "Role not granted");
+        roles.retain(|r| *r != role);
+        if roles.is_empty() {
+            self.data_mut().roles.remove(&account_id);
+        } else {
+            self.data_mut().roles.insert(&account_id, &roles);
+        }
+        Event::RoleRevoked {
+            account_id: &account_id,
+            role,
+        }
+        .emit();
+    }
+}
@@ -0,0 +1,138 @@
+use crate::*;
+
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum UnlockSchedule {
+    /// Releases `total_balance` pro rata between `start_sec` and the lock's
+    /// `unlock_time_sec`, with nothing claimable before `cliff_sec`.
+    Linear {
+        start_sec: u32,
+        cliff_sec: u32,
+        total_balance: U128,
+    },
+    /// Releases each step's amount once its `unlock_time_sec` is reached.
+    Steps { steps: Vec<UnlockStep> },
+}
+
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct UnlockStep {
+    pub unlock_time_sec: u32,
+    pub amount: U128,
+}
+
+/// A step as given in the lock message, sized either as an absolute `amount`
+/// or in basis points of the deposit.
+#[near(serializers = [json])]
+pub struct UnlockStepInput {
+    pub unlock_time_sec: u32,
+    pub amount: Option<U128>,
+    pub bps: Option<u32>,
+}
+
+impl UnlockSchedule {
+    pub fn new_linear(
+        total_balance: U128,
+        start_sec: u32,
+        cliff_sec: Option<u32>,
+        end_sec: u32,
+    ) -> Self {
+        let cliff_sec = cliff_sec.unwrap_or(start_sec);
+        require!(
+            start_sec < end_sec && start_sec <= cliff_sec && cliff_sec <= end_sec,
+            "Invalid vesting schedule"
+        );
+        Self::Linear {
+            start_sec,
+            cliff_sec,
+            total_balance,
+        }
+    }
+
+    /// Resolves `steps` against the deposited `total_balance`. Steps must be in strictly
+    /// increasing time order and either all use `amount`, summing to the deposit, or all
+    /// use `bps`, summing to 10000. Rounding dust of basis points goes to the last step.
+    pub fn new_steps(total_balance: U128, steps: Vec<UnlockStepInput>) -> Self {
+        require!(
+            !steps.is_empty() && steps.len() <= MAX_UNLOCK_STEP_NUM,
+            "Invalid steps num"
+        );
+        require!(
+            steps
+                .windows(2)
+                .all(|w| w[0].unlock_time_sec < w[1].unlock_time_sec),
+            "Steps not in time order"
+        );
+        let amounts: Vec<u128> = if steps.iter().all(|step| step.amount.is_some() && step.bps.is_none()) {
+            let amounts: Vec<u128> = steps.iter().map(|step| step.amount.unwrap().0).collect();
+            require!(
+                amounts.iter().sum::<u128>() == total_balance.0,
+                "Steps not sum to deposit"
+            );
+            amounts
+        } else if steps.iter().all(|step| step.bps.is_some() && step.amount.is_none()) {
+            require!(
+                steps.iter().map(|step| step.bps.unwrap()).sum::<u32>() == BPS_DENOMINATOR,
+                "Steps not sum to deposit"
+            );
+            let mut amounts: Vec<u128> = steps
+                .iter()
+                .map(|step| {
+                    (U256::from(total_balance.0) * U256::from(step.bps.unwrap())
+                        / U256::from(BPS_DENOMINATOR))
+                    .as_u128()
+                })
+                .collect();
+            let dust = total_balance.0 - amounts.iter().sum::<u128>();
+            *amounts.last_mut().unwrap() += dust;
+            amounts
+        } else {
+            env::panic_str("Invalid step amount");
+        };
+        require!(amounts.iter().all(|amount| *amount > 0), "Invalid step amount");
+        Self::Steps {
+            steps: steps
+                .iter()
+                .zip(amounts)
+                .map(|(step, amount)| UnlockStep {
+                    unlock_time_sec: step.unlock_time_sec,
+                    amount: U128(amount),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn total_balance(&self) -> u128 {
+        match self {
+            Self::Linear { total_balance, .. } => total_balance.0,
+            Self::Steps { steps } => steps.iter().map(|step| step.amount.0).sum(),
+        }
+    }
+
+    /// Amount of the original deposit released by `current_time_sec`, ignoring what
+    /// has already been withdrawn or burnt.
+    pub fn unlocked_balance(&self, unlock_time_sec: u32, current_time_sec: u32) -> u128 {
+        match self {
+            Self::Linear {
+                start_sec,
+                cliff_sec,
+                total_balance,
+            } => {
+                if current_time_sec < *cliff_sec {
+                    0
+                } else if current_time_sec >= unlock_time_sec {
+                    total_balance.0
+                } else {
+                    (U256::from(total_balance.0) * U256::from(current_time_sec - start_sec)
+                        / U256::from(unlock_time_sec - start_sec))
+                    .as_u128()
+                }
+            }
+            Self::Steps { steps } => steps
+                .iter()
+                .filter(|step| step.unlock_time_sec <= current_time_sec)
+                .map(|step| step.amount.0)
+                .sum(),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::*;
+
+/// Contract-wide bookkeeping of a token. The contract should hold at least
+/// `total_locked + pending_transfer + total_lostfound + total_reward` of it.
+#[derive(Default)]
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct TokenStats {
+    pub total_locked: U128,
+    pub locker_num: u64,
+    pub total_burned: U128,
+    /// Sent out by withdraw, burn, revoke or lost-and-found transfers whose callback
+    /// has not run yet.
+    pub pending_transfer: U128,
+    pub total_lostfound: U128,
+    /// Ever received through `ft_on_transfer`/`mft_on_transfer`.
+    pub total_deposited: U128,
+    /// Ever sent out by `sweep_excess`.
+    pub total_swept: U128,
+    /// Funded as rewards and not claimed yet, streamed or not.
+    pub total_reward: U128,
+}
+
+impl TokenStats {
+    pub fn accounted_balance(&self) -> u128 {
+        self.total_locked.0 + self.pending_transfer.0 + self.total_lostfound.0 + self.total_reward.0
+    }
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct TokenReconciliation {
+    pub token_id: String,
+    pub balance: U128,
+    pub accounted_balance: U128,
+    pub surplus: U128,
+    pub deficit: U128,
+}
+
+impl Contract {
+    pub fn internal_get_token_stats(&self, token_id: &String) -> TokenStats {
+        self.data().token_stats.get(token_id).unwrap_or_default()
+    }
+
+    pub fn internal_update_token_stats(&mut self, token_id: &String, f: impl FnOnce(&mut TokenStats)) {
+        let mut token_stats = self.internal_get_token_stats(token_id);
+        f(&mut token_stats);
+        self.data_mut().token_stats.insert(token_id, &token_stats);
+    }
+
+    /// Applies the change of one account's locked balances, as returned by
+    /// `Account::locked_balances`, to the totals. Returns the bytes added to the
+    /// token checkpoints, which the account is charged for.
+    pub fn internal_update_locked_stats(
+        &mut self,
+        prev: &HashMap<String, HashMap<u32, u128>>,
+        current: &HashMap<String, HashMap<u32, u128>>,
+    ) -> u64 {
+        let empty = HashMap::new();
+        let mut checkpoint_storage_usage = 0;
+        for token_id in prev.keys().chain(current.keys().filter(|token_id| !prev.contains_key(*token_id))) {
+            let prev_balances = prev.get(token_id).unwrap_or(&empty);
+            let current_balances = current.get(token_id).unwrap_or(&empty);
+            let prev_balance: u128 = prev_balances.values().sum();
+            let current_balance: u128 = current_balances.values().sum();
+            if prev_balance == current_balance {
+                continue;
+            }
+            self.internal_update_token_stats(token_id, |token_stats| {
+                token_stats.total_locked = U128(
+                    (token_stats.total_locked.0 + current_balance).saturating_sub(prev_balance),
+                );
+                if prev_balance == 0 {
+                    token_stats.locker_num += 1;
+                } else if current_balance == 0 {
+                    token_stats.locker_num = token_stats.locker_num.saturating_sub(1);
+                }
+            });
+            let total_locked = self.internal_get_token_stats(token_id).total_locked.0;
+            checkpoint_storage_usage += self.internal_push_token_checkpoint(token_id, total_locked);
+        }
+        checkpoint_storage_usage
+    }
+
+    pub fn internal_add_pending_transfer(&mut self, token_id: &String, amount: U128) {
+        self.internal_update_token_stats(token_id, |token_stats| {
+            token_stats.pending_transfer = U128(token_stats.pending_transfer.0 + amount.0);
+        });
+    }
+
+    pub fn internal_add_deposited(&mut self, token_id: &String, amount: U128) {
+        self.internal_update_token_stats(token_id, |token_stats| {
+            token_stats.total_deposited = U128(token_stats.total_deposited.0 + amount.0);
+        });
+    }
+
+    pub fn internal_finish_pending_transfer(&mut self, token_id: &String, amount: U128) {
+        self.internal_update_token_stats(token_id, |token_stats| {
+            token_stats.pending_transfer = U128(token_stats.pending_transfer.0.saturating_sub(amount.0));
+        });
+    }
+}
+
+#[near]
+impl Contract {
+    /// Compares the token balance held by this contract with what it accounts for.
+    /// Until all legacy accounts are migrated their locks show up as surplus. This reads the
+    /// balance with a cross-contract call, so it must be called in a transaction, not as a view.
+    pub fn reconcile_token(&mut self, token_id: String) -> Promise {
+        token_balance_of(&token_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_BALANCE_OF)
+                .on_reconcile_token(token_id),
+        )
+    }
+
+    #[private]
+    pub fn on_reconcile_token(&self, token_id: String, #[callback_unwrap] balance: U128) -> TokenReconciliation {
+        let accounted_balance = self.internal_get_token_stats(&token_id).accounted_balance();
+        TokenReconciliation {
+            token_id,
+            balance,
+            accounted_balance: U128(accounted_balance),
+            surplus: U128(balance.0.saturating_sub(accounted_balance)),
+            deficit: U128(accounted_balance.saturating_sub(balance.0)),
+        }
+    }
+
+    /// Sends tokens the contract holds beyond what it accounts for, e.g. sent with a plain
+    /// `ft_transfer`, to `receiver_id`. Locks of legacy accounts are missing from the
+    /// totals, so this waits until `migrate_legacy_accounts` has rewritten all of them.
+    #[payable]
+    pub fn sweep_excess(&mut self, token_id: String, receiver_id: AccountId) -> Promise {
+        assert_one_yocto();
+        self.assert_owner();
+        require!(self.data().legacy_account_num == 0, "Legacy accounts not migrated");
+        let token_stats = self.internal_get_token_stats(&token_id);
+        token_balance_of(&token_id).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(
+                    GAS_FOR_AFTER_BALANCE_OF
+                        .saturating_add(GAS_FOR_TOKEN_TRANSFER)
+                        .saturating_add(GAS_FOR_AFTER_TOKEN_TRANSFER),
+                )
+                .on_sweep_excess(
+                    token_id,
+                    receiver_id,
+                    U128(token_stats.accounted_balance()),
+                    token_stats.total_deposited,
+                    token_stats.total_swept,
+                ),
+        )
+    }
+
+    /// The balance may have been read at any point since `sweep_excess` was called.
+    /// Deposits and sweeps in between are added on top of what was accounted for back
+    /// then, which bounds what is owed at the time of the read from above. A sweep that
+    /// failed since is taken off `total_swept` again, so that difference saturates at zero.
+    #[private]
+    pub fn on_sweep_excess(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        accounted_balance: U128,
+        total_deposited: U128,
+        total_swept: U128,
+        #[callback_unwrap] balance: U128,
+    ) -> U128 {
+        let token_stats = self.internal_get_token_stats(&token_id);
+        let reserved = std::cmp::max(accounted_balance.0, token_stats.accounted_balance())
+            + (token_stats.total_deposited.0 - total_deposited.0)
+            + token_stats.total_swept.0.saturating_sub(total_swept.0);
+        let amount = U128(balance.0.saturating_sub(reserved));
+        if amount.0 == 0 {
+            return amount;
+        }
+        self.internal_add_pending_transfer(&token_id, amount);
+        self.internal_update_token_stats(&token_id, |token_stats| {
+            token_stats.total_swept = U128(token_stats.total_swept.0 + amount.0);
+        });
+        token_transfer(&token_id, &receiver_id, amount).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_AFTER_TOKEN_TRANSFER)
+                .after_excess_transfer(token_id.clone(), receiver_id.clone(), amount),
+        );
+        Event::ExcessSweepStarted {
+            token_id: &token_id,
+            receiver_id: &receiver_id,
+            amount: &amount,
+        }
+        .emit();
+        amount
+    }
+
+    #[private]
+    pub fn after_excess_transfer(&mut self, token_id: String, receiver_id: AccountId, amount: U128) -> bool {
+        let promise_success = is_promise_success();
+        self.internal_finish_pending_transfer(&token_id, amount);
+        if promise_success {
+            Event::ExcessSweepSucceeded {
+                token_id: &token_id,
+                receiver_id: &receiver_id,
+                amount: &amount,
+            }
+            .emit();
+        } else {
+            self.internal_update_token_stats(&token_id, |token_stats| {
+                token_stats.total_swept = U128(token_stats.total_swept.0 - amount.0);
+            });
+            Event::ExcessSweepFailed {
+                token_id: &token_id,
+                receiver_id: &receiver_id,
+                amount: &amount,
+            }
+            .emit();
+        }
+        promise_success
+    }
+
+    /// Rewrites accounts still stored in the pre-position layout, so that their locks
+    /// are counted in the token stats. Returns the number of accounts migrated.
+    #[payable]
+    pub fn migrate_legacy_accounts(&mut self, from_index: Option<u64>, limit: Option<u64>) -> u64 {
+        assert_one_yocto();
+        self.assert_owner();
+        let keys = self.data().accounts.keys_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(keys.len());
+        let account_ids: Vec<AccountId> = (from_index..std::cmp::min(keys.len(), from_index + limit))
+            .map(|index| keys.get(index).unwrap())
+            .collect();
+        let mut migrated = 0;
+        for account_id in account_ids {
+            if let Some(VAccount::V0(account)) = self.data().accounts.get(&account_id) {
+                self.internal_force_set_account(&account_id, account.into());
+                migrated += 1;
+            }
+        }
+        migrated
+    }
+}
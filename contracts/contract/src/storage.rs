@@ -7,51 +7,83 @@ pub const STORAGE_BALANCE_MIN_BOUND: NearToken = NearToken::from_millinear(100);
 
 #[near]
 impl StorageManagement for Contract {
-    #[allow(unused_variables)]
     #[payable]
     fn storage_deposit(
         &mut self,
         account_id: Option<AccountId>,
         registration_only: Option<bool>,
     ) -> StorageBalance {
+        self.assert_running(None, Operation::Storage);
         let amount = env::attached_deposit();
-        let account_id = account_id.unwrap_or_else(|| env::predecessor_account_id());
-        let already_registered = self.internal_get_account(&account_id).is_some();
-        if amount < STORAGE_BALANCE_MIN_BOUND && !already_registered {
-            env::panic_str("Insufficient deposit");
-        }
-
-        if already_registered {
-            if !amount.is_zero() {
-                Promise::new(env::predecessor_account_id()).transfer(amount);
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        if let Some(mut account) = self.internal_get_account(&account_id) {
+            if registration_only {
+                if !amount.is_zero() {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            } else {
+                account.storage_balance = account.storage_balance.saturating_add(amount);
+                self.internal_force_set_account(&account_id, account);
             }
         } else {
-            self.internal_set_account(&account_id, Account::new(&account_id).into());
-            let refund = amount.checked_sub(STORAGE_BALANCE_MIN_BOUND).unwrap();
-            if !refund.is_zero() {
-                Promise::new(env::predecessor_account_id()).transfer(refund);
+            if amount < STORAGE_BALANCE_MIN_BOUND {
+                env::panic_str("Insufficient deposit");
+            }
+            let mut account = Account::new(&account_id);
+            if registration_only {
+                account.storage_balance = STORAGE_BALANCE_MIN_BOUND;
+                let refund = amount.checked_sub(STORAGE_BALANCE_MIN_BOUND).unwrap();
+                if !refund.is_zero() {
+                    Promise::new(env::predecessor_account_id()).transfer(refund);
+                }
+            } else {
+                account.storage_balance = amount;
             }
+            self.internal_set_account(&account_id, account);
             Event::AccountRegister { account_id: &account_id }.emit();
         }
         self.storage_balance_of(account_id).unwrap()
     }
 
-    #[allow(unused_variables)]
     #[payable]
     fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
-        unimplemented!()
+        assert_one_yocto();
+        self.assert_running(None, Operation::Storage);
+        let account_id = env::predecessor_account_id();
+        let mut account = self.internal_unwrap_account(&account_id);
+        let available = std::cmp::min(
+            account.storage_available(),
+            account.storage_balance.saturating_sub(account.storage_sponsored),
+        );
+        let amount = amount.unwrap_or(available);
+        require!(amount <= available, "Storage available not enough");
+        account.storage_balance = account.storage_balance.saturating_sub(amount);
+        self.internal_set_account(&account_id, account);
+        if !amount.is_zero() {
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        self.storage_balance_of(account_id).unwrap()
     }
 
     #[payable]
     fn storage_unregister(&mut self, force: Option<bool>) -> bool {
         assert_one_yocto();
+        self.assert_running(None, Operation::Storage);
         let account_id = env::predecessor_account_id();
         if let Some(account) = self.internal_get_account(&account_id) {
             if !force.unwrap_or(false) {
                 require!(account.locked_tokens.is_empty(), "STILL HAS TOKENS");
             }
-            self.data_mut().accounts.remove(&account_id);
-            Promise::new(account_id.clone()).transfer(STORAGE_BALANCE_MIN_BOUND);
+            self.internal_remove_account(&account_id);
+            let data = self.data_mut();
+            data.storage_sponsor_balance = data
+                .storage_sponsor_balance
+                .saturating_add(account.storage_sponsored);
+            let refund = account.storage_balance.saturating_sub(account.storage_sponsored);
+            if !refund.is_zero() {
+                Promise::new(account_id.clone()).transfer(refund);
+            }
             Event::AccountUnregister { account_id: &account_id }.emit();
             true
         } else {
@@ -62,18 +94,39 @@ impl StorageManagement for Contract {
     fn storage_balance_bounds(&self) -> StorageBalanceBounds {
         StorageBalanceBounds {
             min: STORAGE_BALANCE_MIN_BOUND,
-            max: Some(STORAGE_BALANCE_MIN_BOUND),
+            max: None,
         }
     }
 
     fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
-        if self.internal_get_account(&account_id).is_some() {
-            Some(StorageBalance {
-                total: STORAGE_BALANCE_MIN_BOUND,
-                available: NearToken::from_near(0),
+        self.internal_get_account(&account_id)
+            .map(|account| StorageBalance {
+                total: account.storage_balance,
+                available: account.storage_available(),
             })
-        } else {
-            None
+    }
+}
+
+impl Contract {
+    /// Registers `account_id` on the sponsorship pool's budget, if auto registration is
+    /// on and the pool can cover the minimum storage balance.
+    pub fn internal_register_sponsored_account(&mut self, account_id: &AccountId) -> Option<Account> {
+        let data = self.data_mut();
+        if !data.auto_register || data.storage_sponsor_balance < STORAGE_BALANCE_MIN_BOUND {
+            return None;
+        }
+        data.storage_sponsor_balance = data
+            .storage_sponsor_balance
+            .saturating_sub(STORAGE_BALANCE_MIN_BOUND);
+        let mut account = Account::new(account_id);
+        account.storage_balance = STORAGE_BALANCE_MIN_BOUND;
+        account.storage_sponsored = STORAGE_BALANCE_MIN_BOUND;
+        Event::AccountRegister { account_id }.emit();
+        Event::StorageSponsored {
+            account_id,
+            amount: &STORAGE_BALANCE_MIN_BOUND,
         }
+        .emit();
+        Some(account)
     }
 }
@@ -0,0 +1,128 @@
+use crate::*;
+
+pub const DEFAULT_ADMIN_ACTION_DELAY_SEC: u32 = 24 * 3600;
+pub const MAX_ADMIN_ACTION_DELAY_SEC: u32 = 30 * 24 * 3600;
+
+pub type AdminActionId = u64;
+
+/// Admin changes that only take effect `admin_action_delay_sec` after being proposed,
+/// so users can react to them. Code upgrades go through `stage_upgrade` instead.
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub enum AdminAction {
+    SetBurnAccountId { burn_account_id: AccountId },
+    RemoveTokenWhiteList { token_ids: Vec<AccountId> },
+    /// Executing it only makes `new_owner_id` the pending owner, who still has to accept.
+    ProposeOwner { new_owner_id: AccountId },
+    SetAdminActionDelay { delay_sec: u32 },
+}
+
+impl AdminAction {
+    fn required_role(&self) -> Option<Role> {
+        match self {
+            Self::SetBurnAccountId { .. } => Some(Role::BurnManager),
+            Self::RemoveTokenWhiteList { .. } => Some(Role::WhitelistManager),
+            Self::ProposeOwner { .. } | Self::SetAdminActionDelay { .. } => None,
+        }
+    }
+}
+
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct QueuedAdminAction {
+    pub action_id: AdminActionId,
+    pub action: AdminAction,
+    pub proposer_id: AccountId,
+    pub proposed_time_sec: u32,
+    pub executable_time_sec: u32,
+}
+
+impl Contract {
+    /// Owner-only actions require the owner, the others the matching role.
+    fn assert_admin_action_allowed(&self, action: &AdminAction) {
+        match action.required_role() {
+            Some(role) => self.assert_role(role),
+            None => self.assert_owner(),
+        }
+    }
+
+    /// When something started at `start_time_sec` gets past the admin action delay.
+    pub fn internal_admin_action_time_sec(&self, start_time_sec: u32) -> u32 {
+        start_time_sec.saturating_add(self.data().admin_action_delay_sec)
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn propose_admin_action(&mut self, action: AdminAction) -> AdminActionId {
+        assert_one_yocto();
+        self.assert_admin_action_allowed(&action);
+        if let AdminAction::SetAdminActionDelay { delay_sec } = &action {
+            require!(*delay_sec <= MAX_ADMIN_ACTION_DELAY_SEC, "Delay above MAX_ADMIN_ACTION_DELAY_SEC");
+        }
+        let current_time_sec = nano_to_sec(env::block_timestamp());
+        let executable_time_sec = self.internal_admin_action_time_sec(current_time_sec);
+        let data = self.data_mut();
+        let action_id = data.next_admin_action_id;
+        data.next_admin_action_id += 1;
+        let queued_action = QueuedAdminAction {
+            action_id,
+            action,
+            proposer_id: env::predecessor_account_id(),
+            proposed_time_sec: current_time_sec,
+            executable_time_sec,
+        };
+        data.admin_actions.insert(&action_id, &queued_action);
+        Event::AdminActionProposed {
+            action: &queued_action,
+        }
+        .emit();
+        action_id
+    }
+
+    #[payable]
+    pub fn execute_admin_action(&mut self, action_id: AdminActionId) {
+        assert_one_yocto();
+        let queued_action = self.data_mut().admin_actions.remove(&action_id).expect("Invalid action id");
+        self.assert_admin_action_allowed(&queued_action.action);
+        require!(
+            queued_action.executable_time_sec <= nano_to_sec(env::block_timestamp()),
+            "Action still timelocked"
+        );
+        match &queued_action.action {
+            AdminAction::SetBurnAccountId { burn_account_id } => {
+                self.internal_set_burn_account_id(burn_account_id.clone());
+            }
+            AdminAction::RemoveTokenWhiteList { token_ids } => {
+                self.internal_remove_token_white_list(token_ids.clone());
+            }
+            AdminAction::ProposeOwner { new_owner_id } => {
+                self.internal_propose_owner(new_owner_id.clone());
+            }
+            AdminAction::SetAdminActionDelay { delay_sec } => {
+                self.data_mut().admin_action_delay_sec = *delay_sec;
+            }
+        }
+        Event::AdminActionExecuted {
+            action: &queued_action,
+        }
+        .emit();
+    }
+
+    /// Drops a queued action. Callable by the owner and by whoever proposed it.
+    #[payable]
+    pub fn cancel_admin_action(&mut self, action_id: AdminActionId) {
+        assert_one_yocto();
+        let queued_action = self.data_mut().admin_actions.remove(&action_id).expect("Invalid action id");
+        let predecessor_id = env::predecessor_account_id();
+        require!(
+            predecessor_id == self.data().owner_id || predecessor_id == queued_action.proposer_id,
+            "NOT ALLOWED"
+        );
+        Event::AdminActionCancelled {
+            action: &queued_action,
+        }
+        .emit();
+    }
+}
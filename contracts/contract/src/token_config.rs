@@ -0,0 +1,64 @@
+use crate::*;
+
+/// Per-token settings, keyed like `locked_tokens`, i.e. `mft_contract@token` for MFTs.
+#[derive(Default)]
+#[near(serializers = [borsh, json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct TokenConfig {
+    /// Longest allowed time between now and a lock's unlock time. Also the lock time
+    /// giving voting power equal to the amount; voting power is off for tokens without it.
+    pub max_lock_duration_sec: Option<u32>,
+    /// Whether lock owners may hand their locks to other accounts, see `transfer_lock`.
+    #[serde(default)]
+    pub transferable: bool,
+    /// Smallest position others may put into an account, through `LockFor`, `Grant`,
+    /// `transfer_lock` or a lock NFT transfer. Keeps them from filling its `MAX_LOCK_NUM`
+    /// slots and its storage with dust.
+    #[serde(default)]
+    pub min_third_party_amount: U128,
+}
+
+impl Contract {
+    pub fn internal_get_token_config(&self, token_id: &String) -> TokenConfig {
+        self.data().token_configs.get(token_id).unwrap_or_default()
+    }
+
+    pub fn assert_lock_duration(&self, token_id: &String, unlock_time_sec: u32) {
+        if let Some(max_lock_duration_sec) = self.internal_get_token_config(token_id).max_lock_duration_sec {
+            require!(
+                unlock_time_sec as u64 <= nano_to_sec(env::block_timestamp()) as u64 + max_lock_duration_sec as u64,
+                "Lock duration exceeds max"
+            );
+        }
+    }
+
+    pub fn assert_third_party_amount(&self, token_id: &String, amount: U128) {
+        require!(
+            amount.0 >= self.internal_get_token_config(token_id).min_third_party_amount.0,
+            "Amount below min_third_party_amount"
+        );
+    }
+}
+
+#[near]
+impl Contract {
+    #[payable]
+    pub fn set_token_config(&mut self, token_id: String, config: TokenConfig) {
+        assert_one_yocto();
+        self.assert_role(Role::WhitelistManager);
+        require!(
+            config.max_lock_duration_sec != Some(0),
+            "Invalid max lock duration"
+        );
+        // Letting locks change hands is up to the owner alone.
+        if config.transferable != self.internal_get_token_config(&token_id).transferable {
+            self.assert_owner();
+        }
+        self.data_mut().token_configs.insert(&token_id, &config);
+        Event::TokenConfigUpdated {
+            token_id: &token_id,
+            config: &config,
+        }
+        .emit();
+    }
+}
@@ -4,7 +4,40 @@ use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 
 #[near(serializers = [json])]
 enum TokenReceiverMessage {
-    Lock { unlock_time_sec: u32 },
+    /// Opens a new lock position, or appends to `lock_id` when it is given.
+    Lock { unlock_time_sec: u32, lock_id: Option<LockId> },
+    /// Adds to `lock_id` without touching its unlock time. Matured funds are never
+    /// merged with new ones: once `lock_id` has matured, the deposit opens a separate
+    /// position locked until `unlock_time_sec`, which is only read in that case, and
+    /// the matured lock stays withdrawable as it is.
+    TopUp { lock_id: LockId, unlock_time_sec: Option<u32> },
+    /// Opens a position that vests linearly from `start_sec` to `end_sec`.
+    /// Nothing can be withdrawn before `cliff_sec`, which defaults to `start_sec`.
+    Vest { start_sec: u32, cliff_sec: Option<u32>, end_sec: u32 },
+    /// Opens a position that unlocks in tranches, see `UnlockSchedule::new_steps`.
+    LockSteps { steps: Vec<UnlockStepInput> },
+    /// Opens a new lock position owned by `beneficiary_id`, which defaults to the sender.
+    /// The deposit is refunded when a beneficiary other than the sender is not registered.
+    /// Takes either an absolute `unlock_time_sec` or a `duration_sec` counted from the
+    /// block the deposit is received in.
+    LockFor {
+        beneficiary_id: Option<AccountId>,
+        unlock_time_sec: Option<u32>,
+        duration_sec: Option<u32>,
+    },
+    /// Like `Vest`, but for `beneficiary_id` and revocable by `revoker_id`,
+    /// which defaults to the sender.
+    Grant {
+        beneficiary_id: AccountId,
+        revoker_id: Option<AccountId>,
+        start_sec: u32,
+        cliff_sec: Option<u32>,
+        end_sec: u32,
+    },
+    /// Funds rewards streamed to the lockers of `lock_token_id` over `duration_sec`.
+    /// Rewards not yet streamed are spread over the new period as well, which must not
+    /// lower the rate of a stream still running.
+    FundRewards { lock_token_id: String, duration_sec: u32 },
 }
 
 #[near]
@@ -17,34 +50,7 @@ impl FungibleTokenReceiver for Contract {
     ) -> PromiseOrValue<U128> {
         let token_id = env::predecessor_account_id();
         self.assert_white_list_token(&token_id);
-        let mut account = self.internal_unwrap_account(&sender_id);
-
-        let message = serde_json::from_str::<TokenReceiverMessage>(&msg).expect("INVALID MSG");
-        match message {
-            TokenReceiverMessage::Lock { unlock_time_sec } => {
-                if let Some(lock_info) = account.locked_tokens.get_mut(&token_id.to_string()) {
-                    lock_info.append_lock(amount, unlock_time_sec);
-                    Event::AppendToken {
-                        account_id: &sender_id,
-                        token_id: &token_id.to_string(),
-                        amount: &amount,
-                        unlock_time_sec,
-                    }
-                    .emit();
-                } else {
-                    account.add_lock(&token_id.to_string(), amount, unlock_time_sec);
-                    Event::LockedToken {
-                        account_id: &sender_id,
-                        token_id: &token_id.to_string(),
-                        amount: &amount,
-                        unlock_time_sec,
-                    }
-                    .emit();
-                }
-            }
-        }
-        self.internal_set_account(&sender_id, account);
-        PromiseOrValue::Value(U128(0))
+        self.internal_on_transfer(sender_id, token_id.to_string(), amount, msg)
     }
 }
 
@@ -69,33 +75,198 @@ impl MFTTokenReceiver for Contract {
     ) -> PromiseOrValue<U128> {
         self.assert_white_list_token(&env::predecessor_account_id());
         let token_id = generate_mft_token_id(token_id);
-        let mut account = self.internal_unwrap_account(&sender_id);
+        self.internal_on_transfer(sender_id, token_id, amount, msg)
+    }
+}
 
+impl Contract {
+    fn internal_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        token_id: String,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_running(Some(&token_id), Operation::Lock);
         let message = serde_json::from_str::<TokenReceiverMessage>(&msg).expect("INVALID MSG");
+        if let TokenReceiverMessage::FundRewards { lock_token_id, duration_sec } = &message {
+            self.internal_fund_rewards(&sender_id, lock_token_id, &token_id, amount, *duration_sec);
+            self.internal_add_deposited(&token_id, amount);
+            return PromiseOrValue::Value(U128(0));
+        }
+        let account_id = match &message {
+            TokenReceiverMessage::LockFor { beneficiary_id: Some(beneficiary_id), .. }
+            | TokenReceiverMessage::Grant { beneficiary_id, .. } => beneficiary_id.clone(),
+            _ => sender_id.clone(),
+        };
+        if account_id != sender_id {
+            self.assert_third_party_amount(&token_id, amount);
+        }
+        // Only the sender is registered on the sponsorship pool, so dust locks for made-up
+        // beneficiaries can not drain it.
+        let mut account = if let Some(account) = self.internal_get_account(&account_id) {
+            account
+        } else if account_id == sender_id {
+            self.internal_register_sponsored_account(&account_id)
+                .unwrap_or_else(|| env::panic_str("ACCOUNT NOT REGISTERED"))
+        } else {
+            log!("Beneficiary {} not registered, refund {}", account_id, amount.0);
+            return PromiseOrValue::Value(amount);
+        };
+
         match message {
-            TokenReceiverMessage::Lock { unlock_time_sec } => {
-                if let Some(lock_info) = account.locked_tokens.get_mut(&token_id) {
-                    lock_info.append_lock(amount, unlock_time_sec);
+            TokenReceiverMessage::Lock { unlock_time_sec, lock_id: Some(lock_id) } => {
+                self.assert_lock_duration(&token_id, unlock_time_sec);
+                account
+                    .get_lock_mut(&token_id, lock_id)
+                    .expect("Invalid lock")
+                    .append_lock(amount, unlock_time_sec);
+                Event::AppendToken {
+                    account_id: &account_id,
+                    token_id: &token_id,
+                    lock_id,
+                    amount: &amount,
+                    unlock_time_sec,
+                }
+                .emit();
+            }
+            TokenReceiverMessage::Lock { unlock_time_sec, lock_id: None } => {
+                self.assert_lock_duration(&token_id, unlock_time_sec);
+                let lock_id = self.internal_next_lock_id();
+                account.add_lock(&token_id, lock_id, LockInfo::new(amount, unlock_time_sec));
+                Event::LockedToken {
+                    account_id: &account_id,
+                    token_id: &token_id,
+                    lock_id,
+                    amount: &amount,
+                    unlock_time_sec,
+                }
+                .emit();
+            }
+            TokenReceiverMessage::TopUp { lock_id, unlock_time_sec } => {
+                let lock_info = account.get_lock_mut(&token_id, lock_id).expect("Invalid lock");
+                if nano_to_sec(env::block_timestamp()) < lock_info.unlock_time_sec {
+                    lock_info.top_up(amount);
                     Event::AppendToken {
-                        account_id: &sender_id,
-                        token_id: &token_id.to_string(),
+                        account_id: &account_id,
+                        token_id: &token_id,
+                        lock_id,
                         amount: &amount,
-                        unlock_time_sec,
+                        unlock_time_sec: lock_info.unlock_time_sec,
                     }
                     .emit();
                 } else {
-                    account.add_lock(&token_id, amount, unlock_time_sec);
+                    let unlock_time_sec = unlock_time_sec.expect("Lock already matured, unlock_time_sec required");
+                    self.assert_lock_duration(&token_id, unlock_time_sec);
+                    let lock_id = self.internal_next_lock_id();
+                    account.add_lock(&token_id, lock_id, LockInfo::new(amount, unlock_time_sec));
+                    Event::LockedToken {
+                        account_id: &account_id,
+                        token_id: &token_id,
+                        lock_id,
+                        amount: &amount,
+                        unlock_time_sec,
+                    }
+                    .emit();
+                }
+            }
+            TokenReceiverMessage::Vest { start_sec, cliff_sec, end_sec } => {
+                self.assert_lock_duration(&token_id, end_sec);
+                let schedule = UnlockSchedule::new_linear(amount, start_sec, cliff_sec, end_sec);
+                let lock_id = self.internal_next_lock_id();
+                account.add_lock(
+                    &token_id,
+                    lock_id,
+                    LockInfo::new_with_schedule(amount, end_sec, schedule),
+                );
+                Event::LockedVesting {
+                    account_id: &account_id,
+                    token_id: &token_id,
+                    lock_id,
+                    amount: &amount,
+                    start_sec,
+                    cliff_sec: cliff_sec.unwrap_or(start_sec),
+                    end_sec,
+                }
+                .emit();
+            }
+            TokenReceiverMessage::LockFor { unlock_time_sec, duration_sec, .. } => {
+                let unlock_time_sec = match (unlock_time_sec, duration_sec) {
+                    (Some(unlock_time_sec), None) => unlock_time_sec,
+                    (None, Some(duration_sec)) => nano_to_sec(env::block_timestamp())
+                        .checked_add(duration_sec)
+                        .expect("Invalid duration_sec"),
+                    _ => env::panic_str("Either unlock_time_sec or duration_sec required"),
+                };
+                self.assert_lock_duration(&token_id, unlock_time_sec);
+                let lock_id = self.internal_next_lock_id();
+                account.add_lock(&token_id, lock_id, LockInfo::new(amount, unlock_time_sec));
+                if account_id == sender_id {
                     Event::LockedToken {
-                        account_id: &sender_id,
-                        token_id: &token_id.to_string(),
+                        account_id: &account_id,
+                        token_id: &token_id,
+                        lock_id,
                         amount: &amount,
                         unlock_time_sec,
                     }
                     .emit();
+                } else {
+                    Event::LockedTokenFor {
+                        funder_id: &sender_id,
+                        account_id: &account_id,
+                        token_id: &token_id,
+                        lock_id,
+                        amount: &amount,
+                        unlock_time_sec,
+                    }
+                    .emit();
+                }
+            }
+            TokenReceiverMessage::Grant { revoker_id, start_sec, cliff_sec, end_sec, .. } => {
+                self.assert_lock_duration(&token_id, end_sec);
+                let revoker_id = revoker_id.unwrap_or_else(|| sender_id.clone());
+                let schedule = UnlockSchedule::new_linear(amount, start_sec, cliff_sec, end_sec);
+                let mut lock_info = LockInfo::new_with_schedule(amount, end_sec, schedule);
+                lock_info.revoker_id = Some(revoker_id.clone());
+                let lock_id = self.internal_next_lock_id();
+                account.add_lock(&token_id, lock_id, lock_info);
+                Event::LockedGrant {
+                    funder_id: &sender_id,
+                    account_id: &account_id,
+                    revoker_id: &revoker_id,
+                    token_id: &token_id,
+                    lock_id,
+                    amount: &amount,
+                    start_sec,
+                    cliff_sec: cliff_sec.unwrap_or(start_sec),
+                    end_sec,
+                }
+                .emit();
+            }
+            TokenReceiverMessage::LockSteps { steps } => {
+                let schedule = UnlockSchedule::new_steps(amount, steps);
+                let lock_id = self.internal_next_lock_id();
+                let UnlockSchedule::Steps { steps } = &schedule else { unreachable!() };
+                let unlock_time_sec = steps.last().unwrap().unlock_time_sec;
+                self.assert_lock_duration(&token_id, unlock_time_sec);
+                Event::LockedSteps {
+                    account_id: &account_id,
+                    token_id: &token_id,
+                    lock_id,
+                    amount: &amount,
+                    steps,
                 }
+                .emit();
+                account.add_lock(
+                    &token_id,
+                    lock_id,
+                    LockInfo::new_with_schedule(amount, unlock_time_sec, schedule),
+                );
             }
+            TokenReceiverMessage::FundRewards { .. } => unreachable!(),
         }
-        self.internal_set_account(&sender_id, account);
+        self.internal_set_account(&account_id, account);
+        self.internal_add_deposited(&token_id, amount);
         PromiseOrValue::Value(U128(0))
     }
 }
@@ -0,0 +1,91 @@
+use crate::*;
+use crate::nft::refund_lock_approvals;
+
+impl Contract {
+    /// Moves `amount` of `sender_id`'s lock `lock_id`, all of it by default, to
+    /// `receiver_id` with the same unlock time. A whole lock keeps its id, a part split
+    /// off gets a new one. Returns the receiver's lock id and, for a whole lock, the
+    /// NFT approvals it dropped, which the caller refunds to `sender_id`.
+    pub fn internal_transfer_lock(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        token_id: &String,
+        lock_id: LockId,
+        amount: Option<U128>,
+    ) -> (LockId, HashMap<AccountId, u64>) {
+        self.assert_running(Some(token_id), Operation::Transfer);
+        require!(
+            self.internal_get_token_config(token_id).transferable,
+            "Lock not transferable"
+        );
+        require!(sender_id != receiver_id, "Can not transfer to self");
+        let mut sender = self.internal_unwrap_account(sender_id);
+        let mut receiver = self
+            .internal_get_account(receiver_id)
+            .expect("Receiver not registered");
+
+        let mut lock_info = sender.remove_lock(token_id, lock_id).expect("Invalid lock");
+        require!(
+            lock_info.revoker_id.is_none(),
+            "Revocable lock can not be transferred"
+        );
+        let amount = amount.unwrap_or(lock_info.locked_balance);
+        require!(
+            amount.0 > 0 && amount.0 <= lock_info.locked_balance.0,
+            "Lock balance not enough"
+        );
+        self.assert_third_party_amount(token_id, amount);
+        let (receiver_lock_id, approved_account_ids) = if amount.0 == lock_info.locked_balance.0 {
+            receiver.receive_lock(token_id, lock_id, lock_info);
+            let approved_account_ids = self.internal_move_lock_owner(&mut sender, &mut receiver, lock_id);
+            (lock_id, approved_account_ids)
+        } else {
+            // Splitting a schedule would change what each part releases over time.
+            require!(
+                lock_info.schedule.is_none(),
+                "Scheduled lock can only be transferred whole"
+            );
+            lock_info.locked_balance = U128(lock_info.locked_balance.0 - amount.0);
+            let unlock_time_sec = lock_info.unlock_time_sec;
+            sender.insert_lock(token_id, lock_id, lock_info);
+            let receiver_lock_id = self.internal_next_lock_id();
+            receiver.receive_lock(token_id, receiver_lock_id, LockInfo::new(amount, unlock_time_sec));
+            (receiver_lock_id, HashMap::new())
+        };
+        self.internal_set_account(sender_id, sender);
+        self.internal_set_account(receiver_id, receiver);
+        Event::LockTransferred {
+            account_id: sender_id,
+            receiver_id,
+            token_id,
+            lock_id,
+            receiver_lock_id,
+            amount: &amount,
+        }
+        .emit();
+        (receiver_lock_id, approved_account_ids)
+    }
+}
+
+#[near]
+impl Contract {
+    /// Hands `amount` of the caller's lock `lock_id`, all of it by default, to the
+    /// registered `receiver_id`. Only for tokens configured as transferable. Scheduled
+    /// locks move whole and revocable ones not at all. Returns the receiver's lock id.
+    #[payable]
+    pub fn transfer_lock(
+        &mut self,
+        token_id: String,
+        lock_id: LockId,
+        amount: Option<U128>,
+        receiver_id: AccountId,
+    ) -> LockId {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (receiver_lock_id, approved_account_ids) =
+            self.internal_transfer_lock(&sender_id, &receiver_id, &token_id, lock_id, amount);
+        refund_lock_approvals(sender_id, Some(approved_account_ids));
+        receiver_lock_id
+    }
+}
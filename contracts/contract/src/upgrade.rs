@@ -1,16 +1,89 @@
 use crate::*;
+use near_sdk::{json_types::Base58CryptoHash, IntoStorageKey};
+
+/// Code waiting to be deployed by `deploy_staged_upgrade`. The wasm itself is kept
+/// under `StorageKey::StagedCode` when it was uploaded with `stage_upgrade`.
+#[near(serializers = [borsh])]
+pub struct StagedUpgrade {
+    pub code_hash: CryptoHash,
+    pub staged_time_sec: u32,
+    pub has_code: bool,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct StagedUpgradeInfo {
+    /// Base58 sha256 of the code, as printed by `codehash.sh`.
+    pub code_hash: Base58CryptoHash,
+    pub staged_time_sec: u32,
+    pub deployable_time_sec: u32,
+    pub has_code: bool,
+}
+
+impl Contract {
+    fn internal_stage_upgrade(&mut self, code_hash: CryptoHash, has_code: bool) {
+        if !has_code {
+            env::storage_remove(&StorageKey::StagedCode.into_storage_key());
+        }
+        let staged_time_sec = nano_to_sec(env::block_timestamp());
+        self.data_mut().staged_upgrade = Some(StagedUpgrade {
+            code_hash,
+            staged_time_sec,
+            has_code,
+        });
+        Event::UpgradeStaged {
+            code_hash: &code_hash.into(),
+            has_code,
+            deployable_time_sec: self.internal_admin_action_time_sec(staged_time_sec),
+        }
+        .emit();
+    }
+
+    pub fn internal_get_staged_upgrade(&self) -> Option<StagedUpgradeInfo> {
+        self.data().staged_upgrade.as_ref().map(|staged_upgrade| StagedUpgradeInfo {
+            code_hash: staged_upgrade.code_hash.into(),
+            staged_time_sec: staged_upgrade.staged_time_sec,
+            deployable_time_sec: self.internal_admin_action_time_sec(staged_upgrade.staged_time_sec),
+            has_code: staged_upgrade.has_code,
+        })
+    }
+}
 
 #[near]
 impl Contract {
+    /// Stages code by hash only. The code itself has to be passed to `deploy_staged_upgrade`.
+    /// Replaces whatever was staged before and restarts the delay.
+    #[payable]
+    pub fn stage_upgrade_hash(&mut self, code_hash: Base58CryptoHash) {
+        assert_one_yocto();
+        self.assert_role(Role::Upgrader);
+        self.internal_stage_upgrade(code_hash.into(), false);
+    }
+
+    #[payable]
+    pub fn cancel_staged_upgrade(&mut self) {
+        assert_one_yocto();
+        self.assert_role(Role::Upgrader);
+        let staged_upgrade = self.data_mut().staged_upgrade.take().expect("No staged upgrade");
+        env::storage_remove(&StorageKey::StagedCode.into_storage_key());
+        Event::UpgradeCancelled {
+            code_hash: &staged_upgrade.code_hash.into(),
+        }
+        .emit();
+    }
+
     /// A method to migrate a state during the contract upgrade.
-    /// Can only be called after upgrade method.
+    /// Can only be called after deploy_staged_upgrade method.
     #[private]
     #[init(ignore_state)]
     pub fn migrate_state() -> Self {
         let mut contract: Contract = env::state_read().expect("NOT INIT");
         contract.data = match contract.data {
-            VersionedContractData::V1000(data) => VersionedContractData::V1001(data.into()),
-            VersionedContractData::V1001(data) => VersionedContractData::V1001(data),
+            VersionedContractData::V1000(data) => {
+                VersionedContractData::V1002(ContractDataV1001::from(data).into())
+            }
+            VersionedContractData::V1001(data) => VersionedContractData::V1002(data.into()),
+            VersionedContractData::V1002(data) => VersionedContractData::V1002(data),
         };
         contract
     }
@@ -21,6 +94,7 @@ impl Contract {
     }
 }
 
+#[allow(clippy::module_inception)]
 mod upgrade {
     use near_sdk::{require, Gas};
 
@@ -31,19 +105,74 @@ mod upgrade {
     const GAS_FOR_GET_CONFIG_CALL: Gas = Gas::from_tgas(5);
     const MIN_GAS_FOR_MIGRATE_STATE_CALL: Gas = Gas::from_tgas(10);
 
-    /// Self upgrade and call migrate, optimizes gas by not loading into memory the code.
+    /// Hashes the code in register 0 into register 1, without copying it into memory.
+    unsafe fn code_hash_of_register_0() -> CryptoHash {
+        let mut code_hash = [0u8; 32];
+        sys::sha256(u64::MAX, 0, 1);
+        sys::read_register(1, code_hash.as_mut_ptr() as _);
+        code_hash
+    }
+
+    /// Stores the code for a later `deploy_staged_upgrade` and starts the delay.
     /// Takes as input non serialized set of bytes of the code.
     #[no_mangle]
-    pub extern "C" fn upgrade() {
+    pub extern "C" fn stage_upgrade() {
         env::setup_panic_hook();
-        let contract: Contract = env::state_read().expect("ERR_CONTRACT_IS_NOT_INITIALIZED");
-        contract.assert_owner();
+        let mut contract: Contract = env::state_read().expect("ERR_CONTRACT_IS_NOT_INITIALIZED");
+        contract.assert_role(Role::Upgrader);
+        let staged_code_key = StorageKey::StagedCode.into_storage_key();
+        unsafe {
+            sys::input(0);
+            let code_hash = code_hash_of_register_0();
+            sys::storage_write(
+                staged_code_key.len() as _,
+                staged_code_key.as_ptr() as _,
+                u64::MAX,
+                0,
+                1,
+            );
+            contract.internal_stage_upgrade(code_hash, true);
+        }
+        env::state_write(&contract);
+    }
+
+    /// Self upgrade and call migrate, optimizes gas by not loading into memory the code.
+    /// Deploys the staged code once the admin action delay has passed since it was staged.
+    /// Takes as input non serialized set of bytes of the code, matching the staged hash,
+    /// or nothing when the code was uploaded with `stage_upgrade`.
+    #[no_mangle]
+    pub extern "C" fn deploy_staged_upgrade() {
+        env::setup_panic_hook();
+        let mut contract: Contract = env::state_read().expect("ERR_CONTRACT_IS_NOT_INITIALIZED");
+        contract.assert_role(Role::Upgrader);
+        let staged_upgrade = contract.data_mut().staged_upgrade.take().expect("No staged upgrade");
+        require!(
+            contract.internal_admin_action_time_sec(staged_upgrade.staged_time_sec) <= nano_to_sec(env::block_timestamp()),
+            "Upgrade still timelocked"
+        );
         let current_account_id = env::current_account_id().as_bytes().to_vec();
         let migrate_method_name = b"migrate_state".to_vec();
         let get_metadata_method_name = b"get_metadata".to_vec();
         let empty_args = b"{}".to_vec();
+        let staged_code_key = StorageKey::StagedCode.into_storage_key();
         unsafe {
             sys::input(0);
+            if sys::register_len(0) == 0 {
+                require!(
+                    sys::storage_read(staged_code_key.len() as _, staged_code_key.as_ptr() as _, 0) == 1,
+                    "No staged code"
+                );
+            }
+            require!(
+                code_hash_of_register_0() == staged_upgrade.code_hash,
+                "Code hash mismatch"
+            );
+            sys::storage_remove(staged_code_key.len() as _, staged_code_key.as_ptr() as _, 1);
+            Event::UpgradeDeployed {
+                code_hash: &staged_upgrade.code_hash.into(),
+            }
+            .emit();
+            env::state_write(&contract);
             let promise_id = sys::promise_batch_create(
                 current_account_id.len() as _,
                 current_account_id.as_ptr() as _,
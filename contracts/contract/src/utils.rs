@@ -2,11 +2,48 @@ use crate::*;
 use near_sdk::ext_contract;
 
 pub const GAS_FOR_TOKEN_TRANSFER: Gas = Gas::from_tgas(20);
-pub const GAS_FOR_AFTER_TOKEN_TRANSFER: Gas = Gas::from_tgas(10);
-pub const GAS_FOR_AFTER_TOKEN_BURN: Gas = Gas::from_tgas(10);
+pub const GAS_FOR_TOKEN_TRANSFER_CALL: Gas = Gas::from_tgas(50);
+/// Callbacks that may put tokens back rewrite the whole account, which for an account
+/// at `MAX_LOCK_NUM` locks costs well above 10 Tgas. See `test_callback_gas`.
+pub const GAS_FOR_AFTER_TOKEN_TRANSFER: Gas = Gas::from_tgas(30);
+pub const GAS_FOR_AFTER_TOKEN_BURN: Gas = Gas::from_tgas(30);
+pub const GAS_FOR_BALANCE_OF: Gas = Gas::from_tgas(10);
+pub const GAS_FOR_AFTER_BALANCE_OF: Gas = Gas::from_tgas(10);
+pub const GAS_FOR_NFT_ON_TRANSFER: Gas = Gas::from_tgas(20);
+pub const GAS_FOR_NFT_RESOLVE_TRANSFER: Gas = Gas::from_tgas(20);
+pub const GAS_FOR_NFT_ON_APPROVE: Gas = Gas::from_tgas(10);
+/// Gas `withdraw_all` keeps for itself, mostly for writing the account.
+pub const GAS_FOR_WITHDRAW_ALL: Gas = Gas::from_tgas(20);
+/// Gas `withdraw_all` needs per scheduled transfer, on top of what the
+/// transfer and its callback are given.
+pub const GAS_FOR_WITHDRAW_ALL_ITEM: Gas = Gas::from_tgas(5);
 
 pub const MFT_TAG: &str = "@";
 pub const MAX_LOCK_NUM: usize = 64;
+pub const MAX_UNLOCK_STEP_NUM: usize = 32;
+pub const BPS_DENOMINATOR: u32 = 10000;
+
+#[allow(clippy::all)]
+mod u256 {
+    use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+
+    impl BorshSerialize for U256 {
+        fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+            self.0.serialize(writer)
+        }
+    }
+
+    impl BorshDeserialize for U256 {
+        fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+            Ok(Self(<[u64; 4]>::deserialize_reader(reader)?))
+        }
+    }
+}
+pub use u256::U256;
 
 pub fn nano_to_sec(nano: u64) -> u32 {
     (nano / 10u64.pow(9)) as u32
@@ -16,7 +53,7 @@ pub fn generate_mft_token_id(token_id: String) -> String {
     format!("{}{}{}", env::predecessor_account_id(), MFT_TAG, token_id)
 }
 
-pub fn parse_token_id(token_id: &String) -> (AccountId, Option<String>) {
+pub fn parse_token_id(token_id: &str) -> (AccountId, Option<String>) {
     if let Some((contract_id, mft_token_id)) = token_id.split_once(MFT_TAG) {
         (contract_id.parse().unwrap(), Some(mft_token_id.to_string()))
     } else {
@@ -24,9 +61,64 @@ pub fn parse_token_id(token_id: &String) -> (AccountId, Option<String>) {
     }
 }
 
+/// Sends `amount` of an FT or MFT `token_id` held by this contract to `receiver_id`.
+pub fn token_transfer(token_id: &str, receiver_id: &AccountId, amount: U128) -> Promise {
+    let (contract_id, mft_token_id) = parse_token_id(token_id);
+    if let Some(mft_token_id) = mft_token_id {
+        ext_multi_fungible_token::ext(contract_id)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
+            .mft_transfer(mft_token_id, receiver_id.clone(), amount, None)
+    } else {
+        ext_fungible_token::ext(contract_id)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_TOKEN_TRANSFER)
+            .ft_transfer(receiver_id.clone(), amount, None)
+    }
+}
+
+/// Sends `amount` of an FT or MFT `token_id` held by this contract to `receiver_id`
+/// with `ft_transfer_call`/`mft_transfer_call`. The promise resolves to the used amount.
+pub fn token_transfer_call(token_id: &str, receiver_id: &AccountId, amount: U128, msg: String) -> Promise {
+    let (contract_id, mft_token_id) = parse_token_id(token_id);
+    if let Some(mft_token_id) = mft_token_id {
+        ext_multi_fungible_token::ext(contract_id)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_TOKEN_TRANSFER_CALL)
+            .mft_transfer_call(mft_token_id, receiver_id.clone(), amount, None, msg)
+    } else {
+        ext_fungible_token::ext(contract_id)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .with_static_gas(GAS_FOR_TOKEN_TRANSFER_CALL)
+            .ft_transfer_call(receiver_id.clone(), amount, None, msg)
+    }
+}
+
+/// Queries the balance of an FT or MFT `token_id` held by this contract.
+pub fn token_balance_of(token_id: &str) -> Promise {
+    let (contract_id, mft_token_id) = parse_token_id(token_id);
+    if let Some(mft_token_id) = mft_token_id {
+        ext_multi_fungible_token::ext(contract_id)
+            .with_static_gas(GAS_FOR_BALANCE_OF)
+            .mft_balance_of(mft_token_id, env::current_account_id())
+    } else {
+        ext_fungible_token::ext(contract_id)
+            .with_static_gas(GAS_FOR_BALANCE_OF)
+            .ft_balance_of(env::current_account_id())
+    }
+}
+
 #[ext_contract(ext_fungible_token)]
 pub trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
 }
 
 #[ext_contract(ext_multi_fungible_token)]
@@ -38,6 +130,26 @@ pub trait MultiFungibleToken {
         amount: U128,
         memo: Option<String>,
     );
+    fn mft_transfer_call(
+        &mut self,
+        token_id: String,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+    fn mft_balance_of(&self, token_id: String, account_id: AccountId) -> U128;
+}
+
+#[ext_contract(ext_nft_receiver)]
+pub trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: String,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
 }
 
 #[ext_contract(ext_self)]
@@ -46,6 +158,8 @@ trait ExtSelf {
         &mut self,
         account_id: AccountId,
         token_id: String,
+        lock_id: LockId,
         amount: U128,
-    ) -> bool;
+        is_call: bool,
+    ) -> U128;
 }
@@ -0,0 +1,196 @@
+use crate::*;
+use near_sdk::collections::TreeMap;
+
+/// Unlock times count for voting power rounded down to a multiple of this, so a token's
+/// total voting power only changes slope at epoch boundaries.
+pub const VE_EPOCH_SEC: u32 = 7 * 24 * 3600;
+
+pub fn ve_unlock_time_sec(unlock_time_sec: u32) -> u32 {
+    unlock_time_sec - unlock_time_sec % VE_EPOCH_SEC
+}
+
+/// Vote-escrow weight of `amount` locked until `unlock_time_sec`: the amount times the
+/// time left until the epoch-rounded unlock time, over `max_lock_duration_sec`. Locks
+/// made before the max was configured or lowered can weigh more than their amount.
+pub fn voting_power(amount: u128, unlock_time_sec: u32, current_time_sec: u32, max_lock_duration_sec: u32) -> u128 {
+    let remaining_sec = ve_unlock_time_sec(unlock_time_sec).saturating_sub(current_time_sec);
+    (U256::from(amount) * U256::from(remaining_sec) / U256::from(max_lock_duration_sec)).as_u128()
+}
+
+/// The locks of a token still ahead of their epoch-rounded unlock time at `update_time_sec`,
+/// summed up so that the token's total voting power is linear in time until the next unlock.
+/// The contract pays for it, as there is at most one bucket per future epoch.
+#[near(serializers = [borsh])]
+pub struct VeSupply {
+    pub amount: u128,
+    /// Sum of amount times epoch-rounded unlock time.
+    pub amount_time: U256,
+    pub update_time_sec: u32,
+    /// Amounts by epoch-rounded unlock time, for the unlocks after `update_time_sec`.
+    pub unlock_balances: TreeMap<u32, U128>,
+}
+
+impl VeSupply {
+    fn new(token_id: &str) -> Self {
+        Self {
+            amount: 0,
+            amount_time: U256::zero(),
+            update_time_sec: nano_to_sec(env::block_timestamp()),
+            unlock_balances: TreeMap::new(StorageKey::UnlockBalancesItem {
+                token_id: token_id.to_string(),
+            }),
+        }
+    }
+
+    /// Sum of amount times time left until unlock, i.e. the total voting power times the max
+    /// lock duration. Valid from `update_time_sec` up to the next unlock.
+    pub fn weight_at(&self, time_sec: u32) -> U256 {
+        self.amount_time - U256::from(self.amount) * U256::from(time_sec)
+    }
+
+    /// Retires the unlocks due by `current_time_sec` in order and returns their times.
+    /// `on_unlock` is called before each with the supply still holding it.
+    pub fn advance(&mut self, current_time_sec: u32, mut on_unlock: impl FnMut(&VeSupply, u32)) -> Vec<u32> {
+        let due_unlocks: Vec<(u32, u128)> = self
+            .unlock_balances
+            .iter()
+            .take_while(|(unlock_time_sec, _)| *unlock_time_sec <= current_time_sec)
+            .map(|(unlock_time_sec, balance)| (unlock_time_sec, balance.0))
+            .collect();
+        let mut unlock_time_secs = vec![];
+        for (unlock_time_sec, balance) in due_unlocks {
+            on_unlock(self, unlock_time_sec);
+            self.amount -= balance;
+            self.amount_time -= U256::from(balance) * U256::from(unlock_time_sec);
+            self.update_time_sec = unlock_time_sec;
+            unlock_time_secs.push(unlock_time_sec);
+        }
+        self.update_time_sec = std::cmp::max(self.update_time_sec, current_time_sec);
+        unlock_time_secs
+    }
+}
+
+/// Sums balances by epoch-rounded unlock time, leaving out those not after `current_time_sec`.
+pub fn ve_unlock_balances(balances: &HashMap<u32, u128>, current_time_sec: u32) -> HashMap<u32, u128> {
+    let mut ve_balances = HashMap::new();
+    for (unlock_time_sec, balance) in balances {
+        let unlock_time_sec = ve_unlock_time_sec(*unlock_time_sec);
+        if unlock_time_sec > current_time_sec {
+            *ve_balances.entry(unlock_time_sec).or_insert(0) += balance;
+        }
+    }
+    ve_balances
+}
+
+impl Contract {
+    /// The ve supply of `token_id` as last written.
+    pub fn internal_get_ve_supply(&self, token_id: &String) -> VeSupply {
+        self.data().ve_supplies.get(token_id).unwrap_or_else(|| VeSupply::new(token_id))
+    }
+
+    /// Brings the ve supply of `token_id` up to now and saves it, dropping the retired
+    /// buckets. A ve reward pool of the token is brought along, keeping its indexes at
+    /// each unlock passed.
+    pub fn internal_checkpoint_ve_supply(&mut self, token_id: &String) -> VeSupply {
+        let current_time_sec = nano_to_sec(env::block_timestamp());
+        let mut supply = self.internal_get_ve_supply(token_id);
+        let unlock_time_secs = match self.data().reward_pools.get(token_id).filter(|pool| pool.is_ve) {
+            Some(mut pool) => {
+                let mut unlock_time_secs = vec![];
+                for (unlock_time_sec, indexes) in pool.update_ve(&mut supply, current_time_sec) {
+                    pool.unlock_indexes.insert(&unlock_time_sec, &indexes);
+                    unlock_time_secs.push(unlock_time_sec);
+                }
+                self.data_mut().reward_pools.insert(token_id, &pool);
+                unlock_time_secs
+            }
+            None => supply.advance(current_time_sec, |_, _| {}),
+        };
+        for unlock_time_sec in unlock_time_secs {
+            supply.unlock_balances.remove(&unlock_time_sec);
+        }
+        self.data_mut().ve_supplies.insert(token_id, &supply);
+        supply
+    }
+
+    /// Keeps the per-token ve supplies in sync with one account's change, as returned by
+    /// `Account::locked_balances`.
+    pub fn internal_update_unlock_balances(
+        &mut self,
+        prev: &HashMap<String, HashMap<u32, u128>>,
+        current: &HashMap<String, HashMap<u32, u128>>,
+    ) {
+        let empty = HashMap::new();
+        for token_id in prev.keys().chain(current.keys().filter(|token_id| !prev.contains_key(*token_id))) {
+            self.internal_update_token_unlock_balances(
+                token_id,
+                prev.get(token_id).unwrap_or(&empty),
+                current.get(token_id).unwrap_or(&empty),
+            );
+        }
+    }
+
+    fn internal_update_token_unlock_balances(
+        &mut self,
+        token_id: &String,
+        prev: &HashMap<u32, u128>,
+        current: &HashMap<u32, u128>,
+    ) {
+        if prev == current {
+            return;
+        }
+        let mut supply = self.internal_checkpoint_ve_supply(token_id);
+        // Locks already past their epoch-rounded unlock time were retired or never counted.
+        let prev = ve_unlock_balances(prev, supply.update_time_sec);
+        let current = ve_unlock_balances(current, supply.update_time_sec);
+        for unlock_time_sec in prev.keys().chain(current.keys().filter(|t| !prev.contains_key(*t))) {
+            let prev_balance = prev.get(unlock_time_sec).copied().unwrap_or(0);
+            let current_balance = current.get(unlock_time_sec).copied().unwrap_or(0);
+            if prev_balance == current_balance {
+                continue;
+            }
+            let balance = supply.unlock_balances.get(unlock_time_sec).map_or(0, |b| b.0) + current_balance - prev_balance;
+            if balance == 0 {
+                supply.unlock_balances.remove(unlock_time_sec);
+            } else {
+                supply.unlock_balances.insert(unlock_time_sec, &U128(balance));
+            }
+            supply.amount = supply.amount + current_balance - prev_balance;
+            supply.amount_time = supply.amount_time + U256::from(current_balance) * U256::from(*unlock_time_sec)
+                - U256::from(prev_balance) * U256::from(*unlock_time_sec);
+        }
+        self.data_mut().ve_supplies.insert(token_id, &supply);
+    }
+
+    pub fn internal_get_voting_power(&self, account_id: &AccountId, token_id: &String, current_time_sec: u32) -> u128 {
+        let Some(max_lock_duration_sec) = self.internal_get_token_config(token_id).max_lock_duration_sec else {
+            return 0;
+        };
+        self.internal_get_account(account_id)
+            .and_then(|mut account| account.locked_tokens.remove(token_id))
+            .map(|locks| {
+                locks
+                    .values()
+                    .map(|lock_info| {
+                        voting_power(
+                            lock_info.locked_balance.0,
+                            lock_info.unlock_time_sec,
+                            current_time_sec,
+                            max_lock_duration_sec,
+                        )
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Reads only the unlocks since the supply was last written, one per epoch at most.
+    pub fn internal_get_total_voting_power(&self, token_id: &String, current_time_sec: u32) -> u128 {
+        let Some(max_lock_duration_sec) = self.internal_get_token_config(token_id).max_lock_duration_sec else {
+            return 0;
+        };
+        let mut supply = self.internal_get_ve_supply(token_id);
+        supply.advance(current_time_sec, |_, _| {});
+        (supply.weight_at(current_time_sec) / U256::from(max_lock_duration_sec)).as_u128()
+    }
+}
@@ -1,12 +1,28 @@
 use crate::*;
 
+use std::collections::HashMap;
+
 #[near(serializers = [json])]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
 pub struct Metadata {
     owner_id: AccountId,
+    pending_owner_id: Option<AccountId>,
     current_account_num: u64,
     token_white_list: Vec<AccountId>,
     burn_account_id: Option<AccountId>,
+    auto_register: bool,
+    storage_sponsor_balance: NearToken,
+    admin_action_delay_sec: u32,
+    /// Accounts still stored in the pre-position layout, see `migrate_legacy_accounts`.
+    legacy_account_num: u64,
+}
+
+#[near(serializers = [json])]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+pub struct StorageSponsorInfo {
+    pub auto_register: bool,
+    pub balance: NearToken,
+    pub remaining_account_num: u64,
 }
 
 #[near]
@@ -14,9 +30,24 @@ impl Contract {
     pub fn get_metadata(&self) -> Metadata {
         Metadata {
             owner_id: self.data().owner_id.clone(),
+            pending_owner_id: self.data().pending_owner_id.clone(),
             current_account_num: self.data().accounts.len(),
             token_white_list: self.data().token_white_list.to_vec(),
             burn_account_id: self.data().burn_account_id.clone(),
+            auto_register: self.data().auto_register,
+            storage_sponsor_balance: self.data().storage_sponsor_balance,
+            admin_action_delay_sec: self.data().admin_action_delay_sec,
+            legacy_account_num: self.data().legacy_account_num,
+        }
+    }
+
+    /// Remaining budget of the sponsorship pool and how many more accounts it can register.
+    pub fn get_storage_sponsor(&self) -> StorageSponsorInfo {
+        let balance = self.data().storage_sponsor_balance;
+        StorageSponsorInfo {
+            auto_register: self.data().auto_register,
+            balance,
+            remaining_account_num: (balance.as_yoctonear() / STORAGE_BALANCE_MIN_BOUND.as_yoctonear()) as u64,
         }
     }
 
@@ -24,6 +55,29 @@ impl Contract {
         self.internal_get_account(&account_id)
     }
 
+    pub fn get_lock(&self, account_id: AccountId, token_id: String, lock_id: LockId) -> Option<LockInfo> {
+        self.internal_get_account(&account_id)
+            .and_then(|mut account| account.remove_lock(&token_id, lock_id))
+    }
+
+    /// Amount that can be withdrawn right now from `lock_id`, or from all positions
+    /// of `token_id` when no lock is given.
+    pub fn get_withdrawable(&self, account_id: AccountId, token_id: String, lock_id: Option<LockId>) -> U128 {
+        let current_time_sec = nano_to_sec(env::block_timestamp());
+        let withdrawable = self
+            .internal_get_account(&account_id)
+            .and_then(|mut account| account.locked_tokens.remove(&token_id))
+            .map(|locks| {
+                locks
+                    .iter()
+                    .filter(|(id, _)| lock_id.is_none() || lock_id == Some(**id))
+                    .map(|(_, lock_info)| lock_info.withdrawable_balance(current_time_sec))
+                    .sum()
+            })
+            .unwrap_or(0);
+        U128(withdrawable)
+    }
+
     pub fn get_accounts_paged(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Account> {
         let values = self.data().accounts.values_as_vector();
         let from_index = from_index.unwrap_or(0);
@@ -32,4 +86,113 @@ impl Contract {
             .map(|index| values.get(index).unwrap().into())
             .collect()
     }
+
+    pub fn get_lostfound(&self, account_id: AccountId) -> HashMap<String, LostfoundInfo> {
+        self.data().lostfound.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_lostfound_paged(&self, from_index: Option<u64>, limit: Option<u64>) -> HashMap<AccountId, HashMap<String, LostfoundInfo>> {
+        let keys = self.data().lostfound.keys_as_vector();
+        let values = self.data().lostfound.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(keys.len());
+        (from_index..std::cmp::min(keys.len(), from_index + limit))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Locks of legacy accounts are only counted once they are migrated, so the totals
+    /// are short while `legacy_account_num` in `get_metadata` is not zero.
+    pub fn get_token_stats(&self, token_id: String) -> TokenStats {
+        self.internal_get_token_stats(&token_id)
+    }
+
+    pub fn get_token_stats_paged(&self, from_index: Option<u64>, limit: Option<u64>) -> HashMap<String, TokenStats> {
+        let keys = self.data().token_stats.keys_as_vector();
+        let values = self.data().token_stats.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(keys.len());
+        (from_index..std::cmp::min(keys.len(), from_index + limit))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    pub fn get_roles(&self, account_id: AccountId) -> Vec<Role> {
+        if account_id == self.data().owner_id {
+            return vec![Role::WhitelistManager, Role::PauseGuardian, Role::Upgrader, Role::BurnManager];
+        }
+        self.data().roles.get(&account_id).unwrap_or_default()
+    }
+
+    /// Roles explicitly granted, which excludes the owner's implicit ones.
+    pub fn get_roles_paged(&self, from_index: Option<u64>, limit: Option<u64>) -> HashMap<AccountId, Vec<Role>> {
+        let keys = self.data().roles.keys_as_vector();
+        let values = self.data().roles.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(keys.len());
+        (from_index..std::cmp::min(keys.len(), from_index + limit))
+            .map(|index| (keys.get(index).unwrap(), values.get(index).unwrap()))
+            .collect()
+    }
+
+    /// Operations paused contract-wide, or for `token_id` alone when given.
+    pub fn get_paused_operations(&self, token_id: Option<String>) -> Vec<Operation> {
+        self.internal_get_paused_operations(token_id.as_ref())
+    }
+
+    pub fn get_admin_action(&self, action_id: AdminActionId) -> Option<QueuedAdminAction> {
+        self.data().admin_actions.get(&action_id)
+    }
+
+    /// Admin actions proposed but neither executed nor cancelled yet.
+    pub fn get_admin_actions_paged(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<QueuedAdminAction> {
+        let values = self.data().admin_actions.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(values.len());
+        (from_index..std::cmp::min(values.len(), from_index + limit))
+            .map(|index| values.get(index).unwrap())
+            .collect()
+    }
+
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgradeInfo> {
+        self.internal_get_staged_upgrade()
+    }
+
+    pub fn get_token_config(&self, token_id: String) -> TokenConfig {
+        self.internal_get_token_config(&token_id)
+    }
+
+    /// Vote-escrow power of `account_id` in `token_id` right now, see `voting_power`.
+    pub fn get_voting_power(&self, account_id: AccountId, token_id: String) -> U128 {
+        U128(self.internal_get_voting_power(&account_id, &token_id, nano_to_sec(env::block_timestamp())))
+    }
+
+    pub fn get_total_voting_power(&self, token_id: String) -> U128 {
+        U128(self.internal_get_total_voting_power(&token_id, nano_to_sec(env::block_timestamp())))
+    }
+
+    /// Locked balance of `account_id` in `token_id` at `timestamp_sec`, or `None` when
+    /// the account is gone or that part of its history is unknown, i.e. evicted or from
+    /// before the upgrade for legacy accounts.
+    pub fn get_locked_at(&self, account_id: AccountId, token_id: String, timestamp_sec: u32) -> Option<U128> {
+        let account = self.internal_get_account(&account_id)?;
+        account
+            .checkpoints
+            .get(&token_id)
+            .map_or(Some(0), |checkpoints| checkpoints.balance_at(timestamp_sec))
+            .map(U128)
+    }
+
+    pub fn get_total_locked_at(&self, token_id: String, timestamp_sec: u32) -> U128 {
+        U128(self.internal_get_total_locked_at(&token_id, timestamp_sec))
+    }
+
+    pub fn get_reward_pool(&self, lock_token_id: String) -> Option<RewardPoolInfo> {
+        self.data().reward_pools.get(&lock_token_id).map(|pool| pool.into())
+    }
+
+    /// Rewards `account_id` can claim right now, per reward token.
+    pub fn get_unclaimed_rewards(&self, account_id: AccountId) -> HashMap<String, U128> {
+        self.internal_get_unclaimed_rewards(&account_id)
+    }
 }
@@ -1,11 +1,15 @@
-use contract::{nano_to_sec, Metadata};
-use near_sdk::{json_types::U128, serde_json::json, AccountId, NearToken};
-use near_workspaces::{result::{ExecutionFinalResult, Result}, Account, Contract};
+use contract::{nano_to_sec, AdminActionId, LockId, QueuedAdminAction, StagedUpgradeInfo, Metadata, Operation, Role, StorageSponsorInfo, TokenReconciliation, TokenStats, LostfoundInfo, GAS_FOR_AFTER_TOKEN_TRANSFER, MAX_LOCK_NUM, VE_EPOCH_SEC, ve_unlock_time_sec};
+use near_contract_standards::storage_management::StorageBalance;
+use near_sdk::{json_types::{Base58CryptoHash, U128}, serde_json::{self, json}, AccountId, NearToken};
+use sha2::{Digest, Sha256};
+use near_workspaces::{network::Sandbox, operations::Function, result::{ExecutionFinalResult, Result}, types::Gas, Account, Contract, Worker};
+use std::collections::HashMap;
 use contract::Account as ContractAccount;
 
 const FT_WASM: &str = "../../res/mock_ft.wasm";
 const MFT_WASM: &str = "../../res/mock_mft.wasm";
 const TOKEN_LOCKER_WASM: &str = "../../res/token_locker.wasm";
+const TOKEN_LOCKER_RELEASE_WASM: &str = "../../releases/token_locker_release.wasm";
 
 #[tokio::test]
 async fn test_base() -> Result<()> {
@@ -19,9 +23,9 @@ async fn test_base() -> Result<()> {
 
     check!(extend_token_white_list(&token_locker_contract, &root, vec![ft_token_contract.id(), mft_token_contract.id()]));
 
-    check!(logs storage_deposit(&token_locker_contract, &alice.id()));
-    check!(storage_deposit(&ft_token_contract, &alice.id()));
-    check!(storage_deposit(&ft_token_contract, &token_locker_contract.id()));
+    check!(logs storage_deposit(&token_locker_contract, alice.id()));
+    check!(storage_deposit(&ft_token_contract, alice.id()));
+    check!(storage_deposit(&ft_token_contract, token_locker_contract.id()));
 
     check!(view get_metadata(&token_locker_contract));
 
@@ -52,66 +56,1678 @@ async fn test_base() -> Result<()> {
     check!(view "token_locker_contract mft" mft_balance_of(&mft_token_contract, ":0".to_string(), token_locker_contract.id()));
 
     check!(view get_account(&token_locker_contract,alice.id()));
+    let ft_lock_ids = get_lock_ids(&token_locker_contract, alice.id(), ft_token_contract.id().as_str()).await?;
+    let mft_lock_ids = get_lock_ids(&token_locker_contract, alice.id(), "mock_mft.test.near@:0").await?;
+    assert_eq!(ft_lock_ids.len(), 2);
+    assert_eq!(mft_lock_ids.len(), 2);
 
-    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), Some(U128(NearToken::from_near(1).as_yoctonear()))), "Token still locked");
-    check!(withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), Some(U128(NearToken::from_near(1).as_yoctonear()))), "Token still locked");
+    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), ft_lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))), "Token still locked");
+    check!(withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), mft_lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))), "Token still locked");
 
     while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
         worker.fast_forward(20).await?;
     }
 
     check!(view "alice ft" ft_balance_of(&ft_token_contract, alice.id()));
-    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), Some(U128(NearToken::from_near(1).as_yoctonear()))));
+    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), ft_lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))));
     check!(view "alice ft" ft_balance_of(&ft_token_contract, alice.id()));
 
     check!(storage_unregister(&token_locker_contract, &alice, None), "STILL HAS TOKENS");
 
-    check!(logs withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), None));
-    check!(logs withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), None));
+    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), ft_lock_ids[0], None), "Invalid lock");
+    check!(logs withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), ft_lock_ids[1], None));
+    for lock_id in mft_lock_ids {
+        check!(logs withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), lock_id, None));
+    }
     check!(view "alice ft" ft_balance_of(&ft_token_contract, alice.id()));
     check!(view "alice mft" mft_balance_of(&mft_token_contract, ":0".to_string(), alice.id()));
 
-    check!(view "token_locker_contract ft" ft_balance_of(&ft_token_contract, token_locker_contract.id()));
-    check!(view "token_locker_contract mft" mft_balance_of(&mft_token_contract, ":0".to_string(), token_locker_contract.id()));
-    check!(view get_account(&token_locker_contract,alice.id()));
-    check!(view get_metadata(&token_locker_contract));
+    check!(view "token_locker_contract ft" ft_balance_of(&ft_token_contract, token_locker_contract.id()));
+    check!(view "token_locker_contract mft" mft_balance_of(&mft_token_contract, ":0".to_string(), token_locker_contract.id()));
+    check!(view get_account(&token_locker_contract,alice.id()));
+    check!(view get_metadata(&token_locker_contract));
+
+    check!(logs storage_unregister(&token_locker_contract, &alice, None));
+    check!(view get_metadata(&token_locker_contract));
+    
+    check!(storage_deposit(&token_locker_contract, alice.id()));
+    let current_timestamp = worker.view_block().await?.timestamp();
+    let unlock_time_sec = nano_to_sec(current_timestamp) + 120;
+    let msg = json!({
+        "Lock": {
+            "unlock_time_sec": unlock_time_sec
+        }
+    }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    check!(storage_unregister(&ft_token_contract, &alice, Some(true)));
+    check!(mft_transfer_call(&mft_token_contract, &alice, ":0".to_string(), token_locker_contract.id(), NearToken::from_near(5).as_yoctonear(), msg.clone()));
+    check!(mft_unregister(&mft_token_contract, &alice, ":0".to_string(), Some(true)));
+    
+    while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
+        worker.fast_forward(20).await?;
+    }
+    check!(view get_account(&token_locker_contract,alice.id()));
+    let ft_lock_ids = get_lock_ids(&token_locker_contract, alice.id(), ft_token_contract.id().as_str()).await?;
+    let mft_lock_ids = get_lock_ids(&token_locker_contract, alice.id(), "mock_mft.test.near@:0").await?;
+    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), ft_lock_ids[0], None), "The account alice.test.near is not registered");
+    check!(withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), mft_lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))), "ERR_RECEIVER_NOT_REGISTERED");
+    assert_eq!(get_lock_ids(&token_locker_contract, alice.id(), ft_token_contract.id().as_str()).await?, ft_lock_ids);
+    check!(view get_account(&token_locker_contract,alice.id()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_vesting() -> Result<()> {
+    let (worker, _, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+
+    let start_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let end_sec = start_sec + 240;
+    let msg = json!({
+        "Vest": {
+            "start_sec": start_sec,
+            "cliff_sec": start_sec + 60,
+            "end_sec": end_sec
+        }
+    }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), msg));
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    assert_eq!(lock_ids.len(), 1);
+    assert_eq!(get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), None).await?.0, 0);
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None), "Token still locked");
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) < start_sec + 120 {
+        worker.fast_forward(20).await?;
+    }
+    let withdrawable = get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), Some(lock_ids[0])).await?;
+    assert!(withdrawable.0 > 0 && withdrawable.0 < NearToken::from_near(40).as_yoctonear());
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], Some(withdrawable)));
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], Some(U128(NearToken::from_near(40).as_yoctonear()))), "Lock balance not enough");
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) < end_sec {
+        worker.fast_forward(20).await?;
+    }
+    check!(logs withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+    assert!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.is_empty());
+
+    let current_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({
+        "LockSteps": {
+            "steps": [
+                { "unlock_time_sec": current_sec, "amount": U128(NearToken::from_near(4).as_yoctonear()) },
+                { "unlock_time_sec": current_sec + 600, "amount": U128(NearToken::from_near(4).as_yoctonear()) },
+            ]
+        }
+    }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg), "Steps not sum to deposit");
+    let msg = json!({
+        "LockSteps": {
+            "steps": [
+                { "unlock_time_sec": current_sec, "bps": 2500 },
+                { "unlock_time_sec": current_sec + 600, "bps": 7500 },
+            ]
+        }
+    }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    assert_eq!(get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), None).await?.0, NearToken::from_millinear(2500).as_yoctonear());
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None));
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None), "Token still locked");
+    check!(view get_account(&token_locker_contract, alice.id()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lock_for() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 120;
+    let msg = json!({
+        "LockFor": {
+            "beneficiary_id": bob.id(),
+            "unlock_time_sec": unlock_time_sec
+        }
+    }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg.clone()));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(90).as_yoctonear());
+    assert!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.is_empty());
+    let lock_ids = get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?;
+    assert_eq!(lock_ids.len(), 1);
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None), "Invalid lock");
+
+    let start_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let end_sec = start_sec + 240;
+    let msg = json!({
+        "Grant": {
+            "beneficiary_id": bob.id(),
+            "start_sec": start_sec,
+            "end_sec": end_sec
+        }
+    }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), msg));
+    let grant_id = *get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.last().unwrap();
+    while nano_to_sec(worker.view_block().await?.timestamp()) < start_sec + 120 {
+        worker.fast_forward(20).await?;
+    }
+    check!(revoke(&token_locker_contract, &bob, bob.id(), ft_token_id.clone(), grant_id), "NOT ALLOWED");
+    check!(logs revoke(&token_locker_contract, &alice, bob.id(), ft_token_id.clone(), grant_id));
+    let alice_balance = ft_balance_of(&ft_token_contract, alice.id()).await?.0;
+    assert!(alice_balance > NearToken::from_near(50).as_yoctonear() && alice_balance < NearToken::from_near(90).as_yoctonear());
+    check!(revoke(&token_locker_contract, &alice, bob.id(), ft_token_id.clone(), grant_id), "NOT ALLOWED");
+
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(withdraw(&token_locker_contract, &bob, ft_token_id.clone(), grant_id, None));
+    assert_eq!(
+        ft_balance_of(&ft_token_contract, bob.id()).await?.0 + alice_balance,
+        NearToken::from_near(90).as_yoctonear()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_revoke_failed() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+    let carol = root.create_subaccount("carol").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+
+    // Carol may revoke but can not receive the token yet.
+    let start_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({
+        "Grant": {
+            "beneficiary_id": bob.id(),
+            "revoker_id": carol.id(),
+            "start_sec": start_sec,
+            "end_sec": start_sec + 10000
+        }
+    }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), msg));
+    let grant_id = get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?[0];
+    let outcome = revoke(&token_locker_contract, &carol, bob.id(), ft_token_id.clone(), grant_id).await?;
+    assert!(outcome.is_success());
+    assert!(outcome.logs().iter().any(|log| log.contains("lock_revoke_failed")));
+    let revoked = get_lostfound(&token_locker_contract, carol.id()).await?[&ft_token_id].amount.0;
+    assert!(revoked > 0 && revoked <= NearToken::from_near(40).as_yoctonear());
+    let bob_locked = get_account(&token_locker_contract, bob.id()).await?.locked_tokens[&ft_token_id][&grant_id].locked_balance.0;
+    assert_eq!(bob_locked + revoked, NearToken::from_near(40).as_yoctonear());
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    check!(storage_deposit(&ft_token_contract, carol.id()));
+    check!(claim_lostfound(&token_locker_contract, &carol, ft_token_id.clone()));
+    assert_eq!(ft_balance_of(&ft_token_contract, carol.id()).await?.0, revoked);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lock_for_duration() -> Result<()> {
+    let (worker, _, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 120;
+    let msg = json!({ "LockFor": { "duration_sec": 600, "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg), "Either unlock_time_sec or duration_sec required");
+
+    // A duration is resolved against the block the deposit lands in.
+    let before_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "LockFor": { "duration_sec": 600 } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let after_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+    let lock_unlock_time_sec = get_account(&token_locker_contract, alice.id()).await?.locked_tokens[&ft_token_id][&lock_id].unlock_time_sec;
+    assert!(lock_unlock_time_sec >= before_sec + 600 && lock_unlock_time_sec <= after_sec + 600);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_storage() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+
+    let storage_balance = storage_balance_of(&token_locker_contract, alice.id()).await?.unwrap();
+    assert_eq!(storage_balance.total, NearToken::from_millinear(100));
+    assert!(storage_balance.available < storage_balance.total);
+
+    check!(storage_withdraw(&token_locker_contract, &alice, None));
+    let storage_balance = storage_balance_of(&token_locker_contract, alice.id()).await?.unwrap();
+    assert!(storage_balance.available.is_zero());
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 120;
+    let msg = json!({
+        "Lock": {
+            "unlock_time_sec": unlock_time_sec
+        }
+    }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()), "INSUFFICIENT STORAGE");
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+
+    check!(storage_deposit(&token_locker_contract, alice.id()));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    assert_eq!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.len(), 1);
+    let storage_balance_after_lock = storage_balance_of(&token_locker_contract, alice.id()).await?.unwrap();
+    assert!(storage_balance_after_lock.available < NearToken::from_millinear(100));
+
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(mint_ft(&ft_token_contract, bob.id(), NearToken::from_near(100).as_yoctonear()));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()), "ACCOUNT NOT REGISTERED");
+
+    check!(root
+        .call(token_locker_contract.id(), "deposit_storage_sponsor")
+        .deposit(NearToken::from_near(1))
+        .max_gas()
+        .transact());
+    check!(root
+        .call(token_locker_contract.id(), "set_auto_register")
+        .args_json(json!({ "auto_register": true }))
+        .deposit(NearToken::from_yoctonear(1))
+        .max_gas()
+        .transact());
+    check!(logs ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg));
+    assert_eq!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.len(), 1);
+    let storage_balance = storage_balance_of(&token_locker_contract, bob.id()).await?.unwrap();
+    assert_eq!(storage_balance.total, NearToken::from_millinear(100));
+    let storage_sponsor = token_locker_contract.view("get_storage_sponsor").await?.json::<StorageSponsorInfo>()?;
+    assert_eq!(storage_sponsor.remaining_account_num, 9);
+
+    let msg = json!({ "LockFor": { "beneficiary_id": "carol.test.near", "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg));
+    assert!(storage_balance_of(&token_locker_contract, &"carol.test.near".parse().unwrap()).await?.is_none());
+    let storage_sponsor = token_locker_contract.view("get_storage_sponsor").await?.json::<StorageSponsorInfo>()?;
+    assert_eq!(storage_sponsor.remaining_account_num, 9);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_stats() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(mint_ft(&ft_token_contract, bob.id(), NearToken::from_near(100).as_yoctonear()));
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 120;
+    let msg = json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(3).as_yoctonear(), msg));
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(5).as_yoctonear());
+    assert_eq!(token_stats.total_deposited.0, NearToken::from_near(5).as_yoctonear());
+    assert_eq!(token_stats.locker_num, 2);
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
+        worker.fast_forward(20).await?;
+    }
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))));
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(4).as_yoctonear());
+    assert_eq!(token_stats.pending_transfer.0, 0);
+    assert_eq!(token_stats.locker_num, 2);
+
+    let lock_id = get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?[0];
+    check!(withdraw(&token_locker_contract, &bob, ft_token_id.clone(), lock_id, None));
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(1).as_yoctonear());
+    assert_eq!(token_stats.locker_num, 1);
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.balance.0, NearToken::from_near(1).as_yoctonear());
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sweep_excess() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    check!(storage_deposit(&ft_token_contract, root.id()));
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 10000;
+    let msg = json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    check!(ft_transfer(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(3).as_yoctonear()));
+
+    check!(sweep_excess(&token_locker_contract, &alice, ft_token_id.clone(), alice.id()), "NOT ALLOWED");
+    check!(logs sweep_excess(&token_locker_contract, &root, ft_token_id.clone(), root.id()));
+    assert_eq!(ft_balance_of(&ft_token_contract, root.id()).await?.0, NearToken::from_near(3).as_yoctonear());
+    assert_eq!(ft_balance_of(&ft_token_contract, token_locker_contract.id()).await?.0, NearToken::from_near(10).as_yoctonear());
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    // Nothing left to sweep.
+    check!(sweep_excess(&token_locker_contract, &root, ft_token_id.clone(), root.id()));
+    assert_eq!(ft_balance_of(&ft_token_contract, root.id()).await?.0, NearToken::from_near(3).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pause_and_roles() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let guardian = root.create_subaccount("guardian").initial_balance(NearToken::from_near(10)).transact().await?.unwrap();
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({
+        "Lock": {
+            "unlock_time_sec": unlock_time_sec + 1
+        }
+    }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg.clone()));
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+
+    check!(pause(&token_locker_contract, &guardian, None, vec![Operation::Lock]), "NOT ALLOWED");
+    check!(grant_role(&token_locker_contract, &guardian, guardian.id(), Role::PauseGuardian), "NOT ALLOWED");
+    check!(logs grant_role(&token_locker_contract, &root, guardian.id(), Role::PauseGuardian));
+    assert_eq!(get_roles(&token_locker_contract, guardian.id()).await?, vec![Role::PauseGuardian]);
+    check!(extend_token_white_list(&token_locker_contract, &guardian, vec![ft_token_contract.id()]), "NOT ALLOWED");
+    check!(logs pause(&token_locker_contract, &guardian, Some(ft_token_id.clone()), vec![Operation::Withdraw, Operation::Burn]));
+    check!(pause(&token_locker_contract, &guardian, Some(ft_token_id.clone()), vec![Operation::Storage]), "Storage can only be paused contract-wide");
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None), "Operation paused");
+    check!(resume(&token_locker_contract, &guardian, Some(ft_token_id.clone()), vec![Operation::Withdraw]), "NOT ALLOWED");
+    check!(logs resume(&token_locker_contract, &root, Some(ft_token_id.clone()), vec![Operation::Withdraw]));
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= unlock_time_sec + 1 {
+        worker.fast_forward(20).await?;
+    }
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], Some(U128(NearToken::from_near(1).as_yoctonear()))));
+
+    check!(pause(&token_locker_contract, &guardian, None, vec![Operation::Lock, Operation::Storage]));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg), "Operation paused");
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(91).as_yoctonear());
+    check!(storage_withdraw(&token_locker_contract, &alice, None), "Operation paused");
+
+    check!(logs revoke_role(&token_locker_contract, &root, guardian.id(), Role::PauseGuardian));
+    check!(pause(&token_locker_contract, &guardian, None, vec![Operation::Burn]), "NOT ALLOWED");
+    assert!(get_roles(&token_locker_contract, guardian.id()).await?.is_empty());
+
+    check!(propose_admin_action(&token_locker_contract, &guardian, json!({"ProposeOwner": {"new_owner_id": guardian.id()}})), "NOT ALLOWED");
+    check!(propose_admin_action(&token_locker_contract, &root, json!({"SetAdminActionDelay": {"delay_sec": u32::MAX}})), "Delay above MAX_ADMIN_ACTION_DELAY_SEC");
+    check!(propose_admin_action(&token_locker_contract, &root, json!({"SetBurnAccountId": {"burn_account_id": alice.id()}})));
+    check!(propose_admin_action(&token_locker_contract, &root, json!({"ProposeOwner": {"new_owner_id": guardian.id()}})));
+    let admin_actions = get_admin_actions_paged(&token_locker_contract).await?;
+    assert_eq!(admin_actions.len(), 2);
+    check!(execute_admin_action(&token_locker_contract, &root, admin_actions[1].action_id), "Action still timelocked");
+    check!(cancel_admin_action(&token_locker_contract, &alice, admin_actions[0].action_id), "NOT ALLOWED");
+    check!(logs cancel_admin_action(&token_locker_contract, &root, admin_actions[0].action_id));
+    while nano_to_sec(worker.view_block().await?.timestamp()) < admin_actions[1].executable_time_sec {
+        worker.fast_forward(3600).await?;
+    }
+    check!(logs execute_admin_action(&token_locker_contract, &root, admin_actions[1].action_id));
+    assert!(get_admin_actions_paged(&token_locker_contract).await?.is_empty());
+    check!(accept_ownership(&token_locker_contract, &alice), "NOT ALLOWED");
+    check!(logs accept_ownership(&token_locker_contract, &guardian));
+    check!(accept_ownership(&token_locker_contract, &guardian), "NOT ALLOWED");
+    check!(propose_admin_action(&token_locker_contract, &root, json!({"ProposeOwner": {"new_owner_id": root.id()}})), "NOT ALLOWED");
+    check!(logs pause(&token_locker_contract, &guardian, None, vec![Operation::Burn]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upgrade() -> Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let alice = root.create_subaccount("alice").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    let token_locker_contract = deploy_token_locker(&root).await?;
+    let code = std::fs::read(TOKEN_LOCKER_WASM).unwrap();
+    let code_hash: Base58CryptoHash = <[u8; 32]>::from(Sha256::digest(&code)).into();
+
+    check!(stage_upgrade(&token_locker_contract, &alice, code.clone()), "NOT ALLOWED");
+    check!(logs stage_upgrade_hash(&token_locker_contract, &root, code_hash));
+    check!(deploy_staged_upgrade(&token_locker_contract, &root, vec![]), "No staged code");
+    check!(logs stage_upgrade(&token_locker_contract, &root, code.clone()));
+    let staged_upgrade = get_staged_upgrade(&token_locker_contract).await?.unwrap();
+    assert_eq!(staged_upgrade.code_hash, code_hash);
+    assert!(staged_upgrade.has_code);
+    check!(deploy_staged_upgrade(&token_locker_contract, &root, vec![]), "Upgrade still timelocked");
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) < staged_upgrade.deployable_time_sec {
+        worker.fast_forward(3600).await?;
+    }
+    check!(deploy_staged_upgrade(&token_locker_contract, &root, std::fs::read(FT_WASM).unwrap()), "Code hash mismatch");
+    check!(deploy_staged_upgrade(&token_locker_contract, &alice, vec![]), "NOT ALLOWED");
+    check!(logs deploy_staged_upgrade(&token_locker_contract, &root, vec![]));
+    assert!(get_staged_upgrade(&token_locker_contract).await?.is_none());
+    check!(view get_metadata(&token_locker_contract));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_legacy_upgrade() -> Result<()> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let alice = root.create_subaccount("alice").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    let token_locker_contract = deploy_token_locker_from(&root, TOKEN_LOCKER_RELEASE_WASM).await?;
+    let ft_token_contract = deploy_mock_ft(&root).await?;
+    let ft_token_id = ft_token_contract.id().to_string();
+
+    check!(extend_token_white_list(&token_locker_contract, &root, vec![ft_token_contract.id()]));
+    for account in [&alice, &bob] {
+        check!(storage_deposit(&token_locker_contract, account.id()));
+        check!(storage_deposit(&ft_token_contract, account.id()));
+        check!(mint_ft(&ft_token_contract, account.id(), NearToken::from_near(100).as_yoctonear()));
+    }
+    for account_id in [root.id(), token_locker_contract.id()] {
+        check!(storage_deposit(&ft_token_contract, account_id));
+    }
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = |unlock_time_sec: u32| json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 60)));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(20).as_yoctonear(), msg(current_time_sec + 10000)));
+
+    // The released version only knows the plain owner upgrade.
+    check!(logs legacy_upgrade(&token_locker_contract, &root, std::fs::read(TOKEN_LOCKER_WASM).unwrap()));
+    let metadata = token_locker_contract.view("get_metadata").await?.json::<serde_json::Value>()?;
+    assert_eq!(metadata["legacy_account_num"], 2);
+    let alice_lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    assert_eq!(alice_lock_ids.len(), 1);
+    assert!(alice_lock_ids[0] >= 1 << 52);
+    assert_eq!(get_locked_at(&token_locker_contract, bob.id(), ft_token_id.clone(), current_time_sec).await?, None);
+    // The legacy record is charged against the registration deposit, so it can not be withdrawn.
+    let storage_balance = storage_balance_of(&token_locker_contract, bob.id()).await?.unwrap();
+    assert!(storage_balance.available < storage_balance.total);
+    check!(storage_withdraw(&token_locker_contract, &bob, Some(storage_balance.total)), "Storage available not enough");
+    assert!(nft_tokens_for_owner(&token_locker_contract, bob.id()).await?.is_empty());
+
+    // Legacy locks are not in the stats yet, so they must not be swept as surplus.
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, 0);
+    assert_eq!(token_stats.locker_num, 0);
+    check!(ft_transfer(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(3).as_yoctonear()));
+    check!(sweep_excess(&token_locker_contract, &root, ft_token_id.clone(), root.id()), "Legacy accounts not migrated");
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 60 {
+        worker.fast_forward(20).await?;
+    }
+    check!(logs withdraw(&token_locker_contract, &alice, ft_token_id.clone(), alice_lock_ids[0], None));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(97).as_yoctonear());
+    let metadata = token_locker_contract.view("get_metadata").await?.json::<serde_json::Value>()?;
+    assert_eq!(metadata["legacy_account_num"], 1);
+    check!(sweep_excess(&token_locker_contract, &root, ft_token_id.clone(), root.id()), "Legacy accounts not migrated");
+
+    check!(migrate_legacy_accounts(&token_locker_contract, &alice), "NOT ALLOWED");
+    check!(migrate_legacy_accounts(&token_locker_contract, &root));
+    let metadata = token_locker_contract.view("get_metadata").await?.json::<serde_json::Value>()?;
+    assert_eq!(metadata["legacy_account_num"], 0);
+    assert_eq!(nft_tokens_for_owner(&token_locker_contract, bob.id()).await?.len(), 1);
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(20).as_yoctonear());
+    assert_eq!(token_stats.locker_num, 1);
+
+    check!(logs sweep_excess(&token_locker_contract, &root, ft_token_id.clone(), root.id()));
+    assert_eq!(ft_balance_of(&ft_token_contract, root.id()).await?.0, NearToken::from_near(3).as_yoctonear());
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.balance.0, NearToken::from_near(20).as_yoctonear());
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_voting_power_and_checkpoints() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(mint_ft(&ft_token_contract, bob.id(), NearToken::from_near(100).as_yoctonear()));
+    let max_lock_duration_sec = 4 * VE_EPOCH_SEC;
+    check!(set_token_config(&token_locker_contract, &alice, ft_token_id.clone(), json!({"max_lock_duration_sec": max_lock_duration_sec})), "NOT ALLOWED");
+    check!(logs set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"max_lock_duration_sec": max_lock_duration_sec})));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = |unlock_time_sec: u32| json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 5 * VE_EPOCH_SEC)), "Lock duration exceeds max");
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 500)));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 2 * VE_EPOCH_SEC)));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 3 * VE_EPOCH_SEC)));
+
+    // Unlock times are rounded down to the epoch, which takes up to one epoch off the time left.
+    let alice_power = get_voting_power(&token_locker_contract, alice.id(), ft_token_id.clone()).await?.0;
+    let bob_power = get_voting_power(&token_locker_contract, bob.id(), ft_token_id.clone()).await?.0;
+    assert!(alice_power > NearToken::from_millinear(2500).as_yoctonear() && alice_power <= NearToken::from_near(5).as_yoctonear());
+    assert!(bob_power > alice_power && bob_power <= NearToken::from_millinear(7500).as_yoctonear());
+    // Power decays between the views, so the total is only compared approximately.
+    let total_power = get_total_voting_power(&token_locker_contract, ft_token_id.clone()).await?.0;
+    assert!(total_power <= alice_power + bob_power && total_power * 100 >= (alice_power + bob_power) * 99);
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) < current_time_sec + 500 {
+        worker.fast_forward(100).await?;
+    }
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    check!(withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], Some(U128(NearToken::from_near(4).as_yoctonear()))));
+    let withdraw_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let alice_power = get_voting_power(&token_locker_contract, alice.id(), ft_token_id.clone()).await?.0;
+    let bob_power = get_voting_power(&token_locker_contract, bob.id(), ft_token_id.clone()).await?.0;
+    let total_power = get_total_voting_power(&token_locker_contract, ft_token_id.clone()).await?.0;
+    assert!(total_power <= alice_power + bob_power && total_power * 100 >= (alice_power + bob_power) * 99);
+
+    assert_eq!(get_locked_at(&token_locker_contract, alice.id(), ft_token_id.clone(), current_time_sec - 1).await?, Some(U128(0)));
+    assert_eq!(get_locked_at(&token_locker_contract, alice.id(), ft_token_id.clone(), current_time_sec + 400).await?, Some(U128(NearToken::from_near(20).as_yoctonear())));
+    assert_eq!(get_locked_at(&token_locker_contract, alice.id(), ft_token_id.clone(), withdraw_time_sec).await?, Some(U128(NearToken::from_near(16).as_yoctonear())));
+    assert_eq!(get_total_locked_at(&token_locker_contract, ft_token_id.clone(), current_time_sec + 400).await?.0, NearToken::from_near(30).as_yoctonear());
+    assert_eq!(get_total_locked_at(&token_locker_contract, ft_token_id.clone(), withdraw_time_sec).await?.0, NearToken::from_near(26).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_total_voting_power_cost() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    check!(set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"max_lock_duration_sec": 4 * VE_EPOCH_SEC})));
+
+    // Every lock unlocks at a different second, but they share a few epoch buckets.
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    for i in 0..40 {
+        let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + VE_EPOCH_SEC + i * 3001 } }).to_string();
+        check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg));
+    }
+    let alice_power = get_voting_power(&token_locker_contract, alice.id(), ft_token_id.clone()).await?.0;
+    let total_power = get_total_voting_power(&token_locker_contract, ft_token_id.clone()).await?.0;
+    assert!(alice_power > 0);
+    assert!(total_power * 100 >= alice_power * 99 && total_power <= alice_power + 40);
+
+    let outcome = alice
+        .call(token_locker_contract.id(), "get_total_voting_power")
+        .args_json(json!({ "token_id": ft_token_id }))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(outcome.is_success());
+    assert!(outcome.total_gas_burnt < Gas::from_tgas(5));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rewards() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(mint_ft(&ft_token_contract, bob.id(), NearToken::from_near(100).as_yoctonear()));
+    check!(storage_deposit(&ft_token_contract, root.id()));
+    check!(mint_ft(&ft_token_contract, root.id(), NearToken::from_near(100).as_yoctonear()));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg.clone()));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(30).as_yoctonear(), msg));
+
+    // Lockers from before the pool hold shares from the start.
+    let fund_msg = json!({ "FundRewards": { "lock_token_id": ft_token_id, "duration_sec": 100 } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &root, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), fund_msg));
+    let reward_pool = get_reward_pool(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(reward_pool["total_shares"], json!(NearToken::from_near(40).as_yoctonear().to_string()));
+    let dust_fund_msg = json!({ "FundRewards": { "lock_token_id": ft_token_id, "duration_sec": 1_000_000_000 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), 1, dust_fund_msg), "Reward rate can not decrease");
+    assert_eq!(ft_balance_of(&ft_token_contract, bob.id()).await?.0, NearToken::from_near(70).as_yoctonear());
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 200 {
+        worker.fast_forward(100).await?;
+    }
+    let alice_rewards = get_unclaimed_rewards(&token_locker_contract, alice.id()).await?;
+    let bob_rewards = get_unclaimed_rewards(&token_locker_contract, bob.id()).await?;
+    let alice_reward = alice_rewards[&ft_token_id].0;
+    let bob_reward = bob_rewards[&ft_token_id].0;
+    assert!(alice_reward <= NearToken::from_near(10).as_yoctonear());
+    assert!(alice_reward >= NearToken::from_near(10).as_yoctonear() - 100);
+    assert!(bob_reward <= NearToken::from_near(30).as_yoctonear());
+    assert!(bob_reward >= NearToken::from_near(30).as_yoctonear() - 100);
+
+    let balance = ft_balance_of(&ft_token_contract, alice.id()).await?.0;
+    check!(logs claim_rewards(&token_locker_contract, &alice, ft_token_id.clone()));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, balance + alice_reward);
+    assert!(get_unclaimed_rewards(&token_locker_contract, alice.id()).await?.is_empty());
+    check!(claim_rewards(&token_locker_contract, &alice, ft_token_id.clone()), "No rewards");
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_reward.0, NearToken::from_near(40).as_yoctonear() - alice_reward);
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(40).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ve_rewards() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    check!(mint_ft(&ft_token_contract, bob.id(), NearToken::from_near(100).as_yoctonear()));
+    check!(storage_deposit(&ft_token_contract, root.id()));
+    check!(mint_ft(&ft_token_contract, root.id(), NearToken::from_near(100).as_yoctonear()));
+    check!(set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"max_lock_duration_sec": 4 * VE_EPOCH_SEC})));
+
+    // Same amounts, but bob locks for longer and weighs more.
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = |unlock_time_sec: u32| json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    let alice_unlock_time_sec = current_time_sec + 2 * VE_EPOCH_SEC;
+    let bob_unlock_time_sec = current_time_sec + 4 * VE_EPOCH_SEC - 1000;
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(alice_unlock_time_sec)));
+    check!(ft_transfer_call(&ft_token_contract, &bob, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(bob_unlock_time_sec)));
+
+    let fund_msg = json!({ "FundRewards": { "lock_token_id": ft_token_id, "duration_sec": 100 } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &root, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), fund_msg));
+    let reward_pool = get_reward_pool(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(reward_pool["is_ve"], json!(true));
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 200 {
+        worker.fast_forward(100).await?;
+    }
+    let alice_reward = get_unclaimed_rewards(&token_locker_contract, alice.id()).await?[&ft_token_id].0;
+    let bob_reward = get_unclaimed_rewards(&token_locker_contract, bob.id()).await?[&ft_token_id].0;
+    assert!(alice_reward + bob_reward <= NearToken::from_near(40).as_yoctonear());
+    assert!(alice_reward + bob_reward >= NearToken::from_near(40).as_yoctonear() - 1000);
+    // Rewards go by the time left until the epoch-rounded unlock times, give or take the
+    // few hundred seconds the stream ran.
+    let alice_weight = (ve_unlock_time_sec(alice_unlock_time_sec) - current_time_sec) as u128;
+    let bob_weight = (ve_unlock_time_sec(bob_unlock_time_sec) - current_time_sec) as u128;
+    let expected_alice_reward = NearToken::from_near(40).as_yoctonear() / (alice_weight + bob_weight) * alice_weight;
+    assert!(bob_reward > alice_reward);
+    assert!(alice_reward * 100 >= expected_alice_reward * 99 && alice_reward * 99 <= expected_alice_reward * 100);
+
+    let balance = ft_balance_of(&ft_token_contract, alice.id()).await?.0;
+    check!(logs claim_rewards(&token_locker_contract, &alice, ft_token_id.clone()));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, balance + alice_reward);
+    assert_eq!(get_unclaimed_rewards(&token_locker_contract, bob.id()).await?[&ft_token_id].0, bob_reward);
+    check!(reclaim_rewards(&token_locker_contract, &root, ft_token_id.clone(), ft_token_id.clone(), root.id()), "Reward pool has shares");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reclaim_rewards() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    check!(storage_deposit(&ft_token_contract, root.id()));
+    check!(mint_ft(&ft_token_contract, root.id(), NearToken::from_near(100).as_yoctonear()));
+
+    let fund_msg = json!({ "FundRewards": { "lock_token_id": "unknown.test.near", "duration_sec": 100 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &root, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), fund_msg), "NOT WHITE LIST TOKEN");
+    assert_eq!(ft_balance_of(&ft_token_contract, root.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+
+    // Nobody locks, so the stream has nobody to go to.
+    let fund_msg = json!({ "FundRewards": { "lock_token_id": ft_token_id, "duration_sec": 100 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &root, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), fund_msg.clone()));
+    let reward_pool = get_reward_pool(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(reward_pool["total_shares"], json!("0"));
+    check!(reclaim_rewards(&token_locker_contract, &alice, ft_token_id.clone(), ft_token_id.clone(), alice.id()), "NOT ALLOWED");
+    check!(logs reclaim_rewards(&token_locker_contract, &root, ft_token_id.clone(), ft_token_id.clone(), root.id()));
+    assert_eq!(ft_balance_of(&ft_token_contract, root.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+    check!(reclaim_rewards(&token_locker_contract, &root, ft_token_id.clone(), ft_token_id.clone(), root.id()), "No reward stream");
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_reward.0, 0);
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 10000;
+    let msg = json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    check!(ft_transfer_call(&ft_token_contract, &root, token_locker_contract.id(), NearToken::from_near(40).as_yoctonear(), fund_msg));
+    check!(reclaim_rewards(&token_locker_contract, &root, ft_token_id.clone(), ft_token_id.clone(), root.id()), "Reward pool has shares");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_withdraw_all() -> Result<()> {
+    let (worker, _, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = |unlock_time_sec: u32| json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg(current_time_sec + 20)));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(20).as_yoctonear(), msg(current_time_sec + 30)));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(30).as_yoctonear(), msg(current_time_sec + 10000)));
+    check!(withdraw_all(&token_locker_contract, &alice, None), "Nothing to withdraw");
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 30 {
+        worker.fast_forward(100).await?;
+    }
+    let outcome = withdraw_all(&token_locker_contract, &alice, Some(vec![ft_token_id.clone()])).await?;
+    assert!(outcome.is_success());
+    let withdrawals = outcome.json::<Vec<serde_json::Value>>()?;
+    assert_eq!(withdrawals.len(), 2);
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(70).as_yoctonear());
+    assert_eq!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.len(), 1);
+    check!(withdraw_all(&token_locker_contract, &alice, None), "Nothing to withdraw");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lostfound() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    check!(storage_deposit(&ft_token_contract, root.id()));
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 60;
+    let msg = json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+    while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
+        worker.fast_forward(20).await?;
+    }
+
+    // Alice leaves the token and the locker while her withdrawal is in flight.
+    check!(ft_transfer(&ft_token_contract, &alice, root.id(), NearToken::from_near(90).as_yoctonear()));
+    check!(storage_unregister(&ft_token_contract, &alice, None));
+    let outcome = alice
+        .batch(token_locker_contract.id())
+        .call(
+            Function::new("withdraw")
+                .args_json(json!({ "token_id": ft_token_id, "lock_id": lock_id }))
+                .deposit(NearToken::from_yoctonear(1))
+                .gas(Gas::from_tgas(150)),
+        )
+        .call(
+            Function::new("storage_unregister")
+                .args_json(json!({}))
+                .deposit(NearToken::from_yoctonear(1))
+                .gas(Gas::from_tgas(50)),
+        )
+        .transact()
+        .await?;
+    assert!(outcome.is_success());
+    println!("{:#?}", outcome.logs());
+    assert!(storage_balance_of(&token_locker_contract, alice.id()).await?.is_none());
+    let lostfound = get_lostfound(&token_locker_contract, alice.id()).await?;
+    assert_eq!(lostfound[&ft_token_id].amount.0, NearToken::from_near(10).as_yoctonear());
+    let lostfound_paged = get_lostfound_paged(&token_locker_contract).await?;
+    assert_eq!(lostfound_paged.len(), 1);
+    assert_eq!(lostfound_paged[alice.id()][&ft_token_id].amount.0, NearToken::from_near(10).as_yoctonear());
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, 0);
+    assert_eq!(token_stats.total_lostfound.0, NearToken::from_near(10).as_yoctonear());
+    let reconciliation = reconcile_token(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert_eq!(reconciliation.surplus.0, 0);
+    assert_eq!(reconciliation.deficit.0, 0);
+
+    check!(sweep_lostfound(&token_locker_contract, &alice, alice.id(), ft_token_id.clone(), alice.id()), "NOT ALLOWED");
+    check!(sweep_lostfound(&token_locker_contract, &root, alice.id(), ft_token_id.clone(), root.id()), "Lostfound not expired");
+
+    // Claiming while still unregistered on the token puts the balance back.
+    let outcome = claim_lostfound(&token_locker_contract, &alice, ft_token_id.clone()).await?;
+    assert!(outcome.is_success());
+    let lostfound = get_lostfound(&token_locker_contract, alice.id()).await?;
+    assert_eq!(lostfound[&ft_token_id].amount.0, NearToken::from_near(10).as_yoctonear());
+
+    check!(storage_deposit(&ft_token_contract, alice.id()));
+    check!(logs claim_lostfound(&token_locker_contract, &alice, ft_token_id.clone()));
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(10).as_yoctonear());
+    assert!(get_lostfound(&token_locker_contract, alice.id()).await?.is_empty());
+    assert!(get_lostfound_paged(&token_locker_contract).await?.is_empty());
+    check!(claim_lostfound(&token_locker_contract, &alice, ft_token_id.clone()), "No lostfound");
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_lostfound.0, 0);
+    assert_eq!(token_stats.pending_transfer.0, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_callback_gas() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 120;
+    let msg = json!({ "Lock": { "unlock_time_sec": unlock_time_sec } }).to_string();
+    for _ in 0..MAX_LOCK_NUM {
+        check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    }
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    assert_eq!(lock_ids.len(), MAX_LOCK_NUM);
+    while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
+        worker.fast_forward(20).await?;
+    }
+
+    // Bob is not registered on the token, so the transfer fails and the callback
+    // has to put the tokens back into the full account.
+    let outcome = withdraw_to(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], U128(NearToken::from_near(1).as_yoctonear()), bob.id(), None).await?;
+    assert!(outcome.is_success());
+    let callback = outcome
+        .receipt_outcomes()
+        .iter()
+        .find(|receipt| receipt.logs.iter().any(|log| log.contains("withdraw_failed")))
+        .unwrap();
+    println!("refund callback gas burnt: {}", callback.gas_burnt);
+    assert!(callback.gas_burnt.as_gas() < GAS_FOR_AFTER_TOKEN_TRANSFER.as_gas());
+    assert_eq!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.len(), MAX_LOCK_NUM);
+
+    let outcome = withdraw(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None).await?;
+    assert!(outcome.is_success());
+    let callback = outcome
+        .receipt_outcomes()
+        .iter()
+        .find(|receipt| receipt.logs.iter().any(|log| log.contains("withdraw_succeeded")))
+        .unwrap();
+    println!("callback gas burnt: {}", callback.gas_burnt);
+    assert!(callback.gas_burnt.as_gas() < GAS_FOR_AFTER_TOKEN_TRANSFER.as_gas());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_withdraw_to_receiver() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 20 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(30).as_yoctonear(), msg));
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 20 {
+        worker.fast_forward(100).await?;
+    }
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+
+    // A plain transfer to someone else.
+    check!(logs withdraw_to(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, U128(NearToken::from_near(10).as_yoctonear()), bob.id(), None));
+    assert_eq!(ft_balance_of(&ft_token_contract, bob.id()).await?.0, NearToken::from_near(10).as_yoctonear());
+
+    // Through ft_transfer_call, relocking for bob.
+    let relock_msg = json!({ "LockFor": { "beneficiary_id": bob.id(), "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    check!(logs withdraw_to(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, U128(NearToken::from_near(10).as_yoctonear()), token_locker_contract.id(), Some(relock_msg)));
+    assert_eq!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.len(), 1);
+
+    // The receiver rejects the message, so the tokens go back into the lock.
+    check!(logs withdraw_to(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, U128(NearToken::from_near(10).as_yoctonear()), token_locker_contract.id(), Some("invalid".to_string())));
+    assert_eq!(get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), Some(lock_id)).await?.0, NearToken::from_near(10).as_yoctonear());
+    assert_eq!(get_token_stats(&token_locker_contract, ft_token_id.clone()).await?.total_locked.0, NearToken::from_near(20).as_yoctonear());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_lock() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(30).as_yoctonear(), msg));
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+
+    check!(transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, None, bob.id()), "Lock not transferable");
+    check!(grant_role(&token_locker_contract, &root, alice.id(), Role::WhitelistManager));
+    check!(set_token_config(&token_locker_contract, &alice, ft_token_id.clone(), json!({"transferable": true})), "NOT ALLOWED");
+    check!(logs set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"transferable": true})));
+
+    check!(transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, None, root.id()), "Receiver not registered");
+    check!(logs transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, Some(U128(NearToken::from_near(10).as_yoctonear())), bob.id()));
+    let bob_lock_ids = get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?;
+    assert_eq!(bob_lock_ids.len(), 1);
+    assert_ne!(bob_lock_ids[0], lock_id);
+    assert_eq!(get_withdrawable(&token_locker_contract, bob.id(), ft_token_id.clone(), None).await?.0, 0);
+
+    check!(logs transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, None, bob.id()));
+    assert!(get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?.is_empty());
+    assert!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.contains(&lock_id));
+    let token_stats = get_token_stats(&token_locker_contract, ft_token_id.clone()).await?;
+    assert_eq!(token_stats.total_locked.0, NearToken::from_near(30).as_yoctonear());
+    assert_eq!(token_stats.locker_num, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_min_third_party_amount() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+    let min_amount = NearToken::from_near(5).as_yoctonear();
+    check!(set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({
+        "transferable": true,
+        "min_third_party_amount": min_amount.to_string(),
+    })));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let lock_for_msg = json!({ "LockFor": { "beneficiary_id": bob.id(), "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    let grant_msg = json!({ "Grant": { "beneficiary_id": bob.id(), "start_sec": current_time_sec, "end_sec": current_time_sec + 10000 } }).to_string();
+    for msg in [&lock_for_msg, &grant_msg] {
+        check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), min_amount - 1, msg.clone()), "Amount below min_third_party_amount");
+    }
+    assert_eq!(ft_balance_of(&ft_token_contract, alice.id()).await?.0, NearToken::from_near(100).as_yoctonear());
+    assert!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.is_empty());
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), min_amount, lock_for_msg));
+    assert_eq!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.len(), 1);
+
+    // Own locks may be of any size, but only hand over the minimum.
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    check!(transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[0], None, bob.id()), "Amount below min_third_party_amount");
+    check!(nft_transfer(&token_locker_contract, &alice, bob.id(), lock_ids[0].to_string(), None), "Amount below min_third_party_amount");
+    check!(transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[1], Some(U128(min_amount - 1)), bob.id()), "Amount below min_third_party_amount");
+    check!(transfer_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_ids[1], Some(U128(min_amount)), bob.id()));
+    assert_eq!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_nft() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    let bob = root.create_subaccount("bob").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    check!(storage_deposit(&token_locker_contract, bob.id()));
+    check!(storage_deposit(&ft_token_contract, bob.id()));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 10000 } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg.clone()));
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(20).as_yoctonear(), msg));
+
+    let metadata = token_locker_contract.call("nft_metadata").view().await?.json::<serde_json::Value>()?;
+    assert_eq!(metadata["spec"], "nft-1.0.0");
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    let tokens = nft_tokens_for_owner(&token_locker_contract, alice.id()).await?;
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0]["token_id"], lock_ids[0].to_string());
+    let extra: serde_json::Value = serde_json::from_str(tokens[0]["metadata"]["extra"].as_str().unwrap()).unwrap();
+    assert_eq!(extra["token_id"], ft_token_id);
+    assert_eq!(extra["amount"], NearToken::from_near(10).as_yoctonear().to_string());
+    assert_eq!(extra["unlock_time_sec"], current_time_sec + 10000);
+
+    let token_id = lock_ids[0].to_string();
+    check!(nft_transfer(&token_locker_contract, &alice, bob.id(), token_id.clone(), None), "Lock not transferable");
+    check!(set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"transferable": true})));
+    check!(nft_transfer(&token_locker_contract, &bob, bob.id(), token_id.clone(), None), "Sender not approved");
+    check!(logs nft_transfer(&token_locker_contract, &alice, bob.id(), token_id.clone(), None));
+    assert_eq!(nft_token(&token_locker_contract, token_id.clone()).await?["owner_id"], bob.id().to_string());
+    assert_eq!(get_lock_ids(&token_locker_contract, bob.id(), &ft_token_id).await?, vec![lock_ids[0]]);
+
+    // An approved account moves it back on bob's behalf.
+    check!(nft_approve(&token_locker_contract, &bob, token_id.clone(), alice.id()));
+    let approval_id = nft_token(&token_locker_contract, token_id.clone()).await?["approved_account_ids"][alice.id().as_str()].as_u64();
+    // Bob paid for the approval and gets its storage back.
+    let bob_balance = bob.view_account().await?.balance;
+    check!(logs nft_transfer(&token_locker_contract, &alice, alice.id(), token_id.clone(), approval_id));
+    assert!(bob.view_account().await?.balance > bob_balance);
+    assert_eq!(nft_tokens_for_owner(&token_locker_contract, alice.id()).await?.len(), 2);
+    assert!(nft_token(&token_locker_contract, token_id.clone()).await?["approved_account_ids"].as_object().unwrap().is_empty());
+    let total_supply = token_locker_contract.call("nft_total_supply").view().await?.json::<U128>()?;
+    assert_eq!(total_supply.0, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_extend_lock() -> Result<()> {
+    let (worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+    check!(set_token_config(&token_locker_contract, &root, ft_token_id.clone(), json!({"max_lock_duration_sec": 1000})));
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 100 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+    let power = get_voting_power(&token_locker_contract, alice.id(), ft_token_id.clone()).await?.0;
+
+    check!(extend_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, current_time_sec + 50), "Invalid unlock_time_sec");
+    check!(extend_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, current_time_sec + 5000), "Lock duration exceeds max");
+    check!(logs extend_lock(&token_locker_contract, &alice, ft_token_id.clone(), lock_id, current_time_sec + 900));
+    let account = get_account(&token_locker_contract, alice.id()).await?;
+    assert_eq!(account.locked_tokens[&ft_token_id][&lock_id].unlock_time_sec, current_time_sec + 900);
+    assert!(get_voting_power(&token_locker_contract, alice.id(), ft_token_id.clone()).await?.0 > power);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_top_up() -> Result<()> {
+    let (worker, _, alice, token_locker_contract, ft_token_contract, ft_token_id) = setup().await?;
+
+    let current_time_sec = nano_to_sec(worker.view_block().await?.timestamp());
+    let msg = json!({ "Lock": { "unlock_time_sec": current_time_sec + 30 } }).to_string();
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), msg));
+    let lock_id = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?[0];
+
+    let top_up_msg = |unlock_time_sec: Option<u32>| json!({ "TopUp": { "lock_id": lock_id, "unlock_time_sec": unlock_time_sec } }).to_string();
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), top_up_msg(None)));
+    let account = get_account(&token_locker_contract, alice.id()).await?;
+    assert_eq!(account.locked_tokens[&ft_token_id][&lock_id].locked_balance.0, NearToken::from_near(20).as_yoctonear());
+    assert_eq!(account.locked_tokens[&ft_token_id][&lock_id].unlock_time_sec, current_time_sec + 30);
+
+    while nano_to_sec(worker.view_block().await?.timestamp()) <= current_time_sec + 30 {
+        worker.fast_forward(100).await?;
+    }
+    // Matured funds stay apart from the new deposit.
+    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), top_up_msg(None)), "unlock_time_sec required");
+    let unlock_time_sec = nano_to_sec(worker.view_block().await?.timestamp()) + 10000;
+    check!(logs ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(10).as_yoctonear(), top_up_msg(Some(unlock_time_sec))));
+    let lock_ids = get_lock_ids(&token_locker_contract, alice.id(), &ft_token_id).await?;
+    assert_eq!(lock_ids.len(), 2);
+    assert_eq!(get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), Some(lock_id)).await?.0, NearToken::from_near(20).as_yoctonear());
+    assert_eq!(get_withdrawable(&token_locker_contract, alice.id(), ft_token_id.clone(), None).await?.0, NearToken::from_near(20).as_yoctonear());
+
+    Ok(())
+}
+
+pub async fn extend_token_white_list(
+    contract: &Contract,
+    sender: &Account,
+    token_ids: Vec<&AccountId>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "extend_token_white_list")
+        .args_json(json!({
+            "token_ids": token_ids,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn legacy_upgrade(
+    contract: &Contract,
+    sender: &Account,
+    code: Vec<u8>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "upgrade")
+        .args(code)
+        .max_gas()
+        .transact()
+        .await
+}
+
+pub async fn migrate_legacy_accounts(
+    contract: &Contract,
+    sender: &Account,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "migrate_legacy_accounts")
+        .args_json(json!({}))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn stage_upgrade(
+    contract: &Contract,
+    sender: &Account,
+    code: Vec<u8>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "stage_upgrade")
+        .args(code)
+        .max_gas()
+        .transact()
+        .await
+}
+
+pub async fn stage_upgrade_hash(
+    contract: &Contract,
+    sender: &Account,
+    code_hash: Base58CryptoHash,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "stage_upgrade_hash")
+        .args_json(json!({
+            "code_hash": code_hash
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn deploy_staged_upgrade(
+    contract: &Contract,
+    sender: &Account,
+    code: Vec<u8>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "deploy_staged_upgrade")
+        .args(code)
+        .max_gas()
+        .transact()
+        .await
+}
+
+pub async fn get_staged_upgrade(
+    contract: &Contract,
+) -> Result<Option<StagedUpgradeInfo>> {
+    contract
+        .call("get_staged_upgrade")
+        .args_json(json!({}))
+        .view()
+        .await?
+        .json::<Option<StagedUpgradeInfo>>()
+}
+
+pub async fn propose_admin_action(
+    contract: &Contract,
+    sender: &Account,
+    action: serde_json::Value,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "propose_admin_action")
+        .args_json(json!({
+            "action": action
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn execute_admin_action(
+    contract: &Contract,
+    sender: &Account,
+    action_id: AdminActionId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "execute_admin_action")
+        .args_json(json!({
+            "action_id": action_id
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn cancel_admin_action(
+    contract: &Contract,
+    sender: &Account,
+    action_id: AdminActionId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "cancel_admin_action")
+        .args_json(json!({
+            "action_id": action_id
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn get_admin_actions_paged(
+    contract: &Contract,
+) -> Result<Vec<QueuedAdminAction>> {
+    contract
+        .call("get_admin_actions_paged")
+        .args_json(json!({}))
+        .view()
+        .await?
+        .json::<Vec<QueuedAdminAction>>()
+}
+
+pub async fn accept_ownership(
+    contract: &Contract,
+    sender: &Account,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "accept_ownership")
+        .args_json(json!({}))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn grant_role(
+    contract: &Contract,
+    sender: &Account,
+    account_id: &AccountId,
+    role: Role,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "grant_role")
+        .args_json(json!({
+            "account_id": account_id,
+            "role": role
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn revoke_role(
+    contract: &Contract,
+    sender: &Account,
+    account_id: &AccountId,
+    role: Role,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "revoke_role")
+        .args_json(json!({
+            "account_id": account_id,
+            "role": role
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn get_roles(
+    contract: &Contract,
+    account_id: &AccountId,
+) -> Result<Vec<Role>> {
+    contract
+        .call("get_roles")
+        .args_json(json!({
+            "account_id": account_id
+        }))
+        .view()
+        .await?
+        .json::<Vec<Role>>()
+}
+
+pub async fn pause(
+    contract: &Contract,
+    sender: &Account,
+    token_id: Option<String>,
+    operations: Vec<Operation>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "pause")
+        .args_json(json!({
+            "token_id": token_id,
+            "operations": operations
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn resume(
+    contract: &Contract,
+    sender: &Account,
+    token_id: Option<String>,
+    operations: Vec<Operation>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "resume")
+        .args_json(json!({
+            "token_id": token_id,
+            "operations": operations
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn withdraw(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String, 
+    lock_id: LockId,
+    amount: Option<U128>
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "withdraw")
+        .args_json(json!({
+            "token_id": token_id,
+            "lock_id": lock_id,
+            "amount": amount
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn withdraw_to(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    lock_id: LockId,
+    amount: U128,
+    receiver_id: &AccountId,
+    msg: Option<String>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "withdraw")
+        .args_json(json!({
+            "token_id": token_id,
+            "lock_id": lock_id,
+            "amount": amount,
+            "receiver_id": receiver_id,
+            "msg": msg,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn withdraw_all(
+    contract: &Contract,
+    sender: &Account,
+    token_ids: Option<Vec<String>>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "withdraw_all")
+        .args_json(json!({
+            "token_ids": token_ids,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn transfer_lock(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    lock_id: LockId,
+    amount: Option<U128>,
+    receiver_id: &AccountId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "transfer_lock")
+        .args_json(json!({
+            "token_id": token_id,
+            "lock_id": lock_id,
+            "amount": amount,
+            "receiver_id": receiver_id,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn nft_transfer(
+    contract: &Contract,
+    sender: &Account,
+    receiver_id: &AccountId,
+    token_id: String,
+    approval_id: Option<u64>,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "nft_transfer")
+        .args_json(json!({
+            "receiver_id": receiver_id,
+            "token_id": token_id,
+            "approval_id": approval_id,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn nft_approve(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    account_id: &AccountId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "nft_approve")
+        .args_json(json!({
+            "token_id": token_id,
+            "account_id": account_id,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_millinear(10))
+        .transact()
+        .await
+}
+
+pub async fn nft_token(
+    contract: &Contract,
+    token_id: String,
+) -> Result<serde_json::Value> {
+    contract
+        .call("nft_token")
+        .args_json(json!({
+            "token_id": token_id
+        }))
+        .view()
+        .await?
+        .json::<serde_json::Value>()
+}
+
+pub async fn nft_tokens_for_owner(
+    contract: &Contract,
+    account_id: &AccountId,
+) -> Result<Vec<serde_json::Value>> {
+    contract
+        .call("nft_tokens_for_owner")
+        .args_json(json!({
+            "account_id": account_id
+        }))
+        .view()
+        .await?
+        .json::<Vec<serde_json::Value>>()
+}
+
+pub async fn extend_lock(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    lock_id: LockId,
+    new_unlock_time_sec: u32,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "extend_lock")
+        .args_json(json!({
+            "token_id": token_id,
+            "lock_id": lock_id,
+            "new_unlock_time_sec": new_unlock_time_sec,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn revoke(
+    contract: &Contract,
+    sender: &Account,
+    account_id: &AccountId,
+    token_id: String,
+    lock_id: LockId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "revoke")
+        .args_json(json!({
+            "account_id": account_id,
+            "token_id": token_id,
+            "lock_id": lock_id
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn get_account(
+    contract: &Contract,
+    account_id: &AccountId,
+) -> Result<ContractAccount> {
+    contract
+        .call("get_account")
+        .args_json(json!({
+            "account_id": account_id
+        }))
+        .view()
+        .await?
+        .json::<ContractAccount>()
+}
+
+pub async fn get_lock_ids(
+    contract: &Contract,
+    account_id: &AccountId,
+    token_id: &str,
+) -> Result<Vec<LockId>> {
+    let account = get_account(contract, account_id).await?;
+    let mut lock_ids: Vec<LockId> = account
+        .locked_tokens
+        .get(token_id)
+        .map(|locks| locks.keys().cloned().collect())
+        .unwrap_or_default();
+    lock_ids.sort();
+    Ok(lock_ids)
+}
+
+pub async fn get_withdrawable(
+    contract: &Contract,
+    account_id: &AccountId,
+    token_id: String,
+    lock_id: Option<LockId>,
+) -> Result<U128> {
+    contract
+        .call("get_withdrawable")
+        .args_json(json!({
+            "account_id": account_id,
+            "token_id": token_id,
+            "lock_id": lock_id
+        }))
+        .view()
+        .await?
+        .json::<U128>()
+}
+
+pub async fn sweep_excess(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    receiver_id: &AccountId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "sweep_excess")
+        .args_json(json!({
+            "token_id": token_id,
+            "receiver_id": receiver_id
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
 
-    check!(logs storage_unregister(&token_locker_contract, &alice, None));
-    check!(view get_metadata(&token_locker_contract));
-    
-    check!(storage_deposit(&token_locker_contract, &alice.id()));
-    let current_timestamp = worker.view_block().await?.timestamp();
-    let unlock_time_sec = nano_to_sec(current_timestamp) + 120;
-    let msg = json!({
-        "Lock": {
-            "unlock_time_sec": unlock_time_sec
-        }
-    }).to_string();
-    check!(ft_transfer_call(&ft_token_contract, &alice, token_locker_contract.id(), NearToken::from_near(1).as_yoctonear(), msg.clone()));
-    check!(storage_unregister(&ft_token_contract, &alice, Some(true)));
-    check!(mft_transfer_call(&mft_token_contract, &alice, ":0".to_string(), token_locker_contract.id(), NearToken::from_near(5).as_yoctonear(), msg.clone()));
-    check!(mft_unregister(&mft_token_contract, &alice, ":0".to_string(), Some(true)));
-    
-    while nano_to_sec(worker.view_block().await?.timestamp()) < unlock_time_sec {
-        worker.fast_forward(20).await?;
-    }
-    check!(view get_account(&token_locker_contract,alice.id()));
-    check!(withdraw(&token_locker_contract, &alice, ft_token_contract.id().to_string(), None), "The account alice.test.near is not registered");
-    check!(withdraw(&token_locker_contract, &alice, "mock_mft.test.near@:0".to_string(), Some(U128(NearToken::from_near(1).as_yoctonear()))), "ERR_RECEIVER_NOT_REGISTERED");
-    check!(view get_account(&token_locker_contract,alice.id()));
+pub async fn set_token_config(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+    config: serde_json::Value,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "set_token_config")
+        .args_json(json!({
+            "token_id": token_id,
+            "config": config
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
 
-    Ok(())
+pub async fn get_voting_power(
+    contract: &Contract,
+    account_id: &AccountId,
+    token_id: String,
+) -> Result<U128> {
+    contract
+        .call("get_voting_power")
+        .args_json(json!({
+            "account_id": account_id,
+            "token_id": token_id
+        }))
+        .view()
+        .await?
+        .json::<U128>()
 }
 
-pub async fn extend_token_white_list(
+pub async fn get_total_voting_power(
+    contract: &Contract,
+    token_id: String,
+) -> Result<U128> {
+    contract
+        .call("get_total_voting_power")
+        .args_json(json!({
+            "token_id": token_id
+        }))
+        .view()
+        .await?
+        .json::<U128>()
+}
+
+pub async fn get_locked_at(
+    contract: &Contract,
+    account_id: &AccountId,
+    token_id: String,
+    timestamp_sec: u32,
+) -> Result<Option<U128>> {
+    contract
+        .call("get_locked_at")
+        .args_json(json!({
+            "account_id": account_id,
+            "token_id": token_id,
+            "timestamp_sec": timestamp_sec
+        }))
+        .view()
+        .await?
+        .json::<Option<U128>>()
+}
+
+pub async fn get_total_locked_at(
+    contract: &Contract,
+    token_id: String,
+    timestamp_sec: u32,
+) -> Result<U128> {
+    contract
+        .call("get_total_locked_at")
+        .args_json(json!({
+            "token_id": token_id,
+            "timestamp_sec": timestamp_sec
+        }))
+        .view()
+        .await?
+        .json::<U128>()
+}
+
+pub async fn claim_rewards(
     contract: &Contract,
     sender: &Account,
-    token_ids: Vec<&AccountId>,
+    reward_token_id: String,
 ) -> Result<ExecutionFinalResult> {
     sender
-        .call(contract.id(), "extend_token_white_list")
+        .call(contract.id(), "claim_rewards")
         .args_json(json!({
-            "token_ids": token_ids,
+            "reward_token_id": reward_token_id,
         }))
         .max_gas()
         .deposit(NearToken::from_yoctonear(1))
@@ -119,17 +1735,19 @@ pub async fn extend_token_white_list(
         .await
 }
 
-pub async fn withdraw(
+pub async fn reclaim_rewards(
     contract: &Contract,
     sender: &Account,
-    token_id: String, 
-    amount: Option<U128>
+    lock_token_id: String,
+    reward_token_id: String,
+    receiver_id: &AccountId,
 ) -> Result<ExecutionFinalResult> {
     sender
-        .call(contract.id(), "withdraw")
+        .call(contract.id(), "reclaim_rewards")
         .args_json(json!({
-            "token_id": token_id,
-            "amount": amount
+            "lock_token_id": lock_token_id,
+            "reward_token_id": reward_token_id,
+            "receiver_id": receiver_id,
         }))
         .max_gas()
         .deposit(NearToken::from_yoctonear(1))
@@ -137,18 +1755,123 @@ pub async fn withdraw(
         .await
 }
 
-pub async fn get_account(
+pub async fn get_reward_pool(
+    contract: &Contract,
+    lock_token_id: String,
+) -> Result<serde_json::Value> {
+    contract
+        .call("get_reward_pool")
+        .args_json(json!({
+            "lock_token_id": lock_token_id
+        }))
+        .view()
+        .await?
+        .json::<serde_json::Value>()
+}
+
+pub async fn get_unclaimed_rewards(
     contract: &Contract,
     account_id: &AccountId,
-) -> Result<ContractAccount> {
+) -> Result<std::collections::HashMap<String, U128>> {
     contract
-        .call("get_account")
+        .call("get_unclaimed_rewards")
         .args_json(json!({
             "account_id": account_id
         }))
         .view()
         .await?
-        .json::<ContractAccount>()
+        .json::<std::collections::HashMap<String, U128>>()
+}
+
+pub async fn claim_lostfound(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "claim_lostfound")
+        .args_json(json!({
+            "token_id": token_id,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn sweep_lostfound(
+    contract: &Contract,
+    sender: &Account,
+    account_id: &AccountId,
+    token_id: String,
+    receiver_id: &AccountId,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "sweep_lostfound")
+        .args_json(json!({
+            "account_id": account_id,
+            "token_id": token_id,
+            "receiver_id": receiver_id,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn get_lostfound(
+    contract: &Contract,
+    account_id: &AccountId,
+) -> Result<HashMap<String, LostfoundInfo>> {
+    contract
+        .call("get_lostfound")
+        .args_json(json!({
+            "account_id": account_id,
+        }))
+        .view()
+        .await?
+        .json::<HashMap<String, LostfoundInfo>>()
+}
+
+pub async fn get_lostfound_paged(
+    contract: &Contract,
+) -> Result<HashMap<AccountId, HashMap<String, LostfoundInfo>>> {
+    contract
+        .call("get_lostfound_paged")
+        .args_json(json!({}))
+        .view()
+        .await?
+        .json::<HashMap<AccountId, HashMap<String, LostfoundInfo>>>()
+}
+
+pub async fn get_token_stats(
+    contract: &Contract,
+    token_id: String,
+) -> Result<TokenStats> {
+    contract
+        .call("get_token_stats")
+        .args_json(json!({
+            "token_id": token_id
+        }))
+        .view()
+        .await?
+        .json::<TokenStats>()
+}
+
+pub async fn reconcile_token(
+    contract: &Contract,
+    sender: &Account,
+    token_id: String,
+) -> Result<TokenReconciliation> {
+    sender
+        .call(contract.id(), "reconcile_token")
+        .args_json(json!({
+            "token_id": token_id
+        }))
+        .max_gas()
+        .transact()
+        .await?
+        .json::<TokenReconciliation>()
 }
 
 pub async fn get_metadata(
@@ -187,6 +1910,25 @@ pub async fn mft_transfer_call(
         .await
 }
 
+pub async fn ft_transfer(
+    contract: &Contract,
+    sender: &Account,
+    receiver_id: &AccountId,
+    amount: u128,
+) -> Result<ExecutionFinalResult> {
+    sender
+        .call(contract.id(), "ft_transfer")
+        .args_json(json!({
+            "receiver_id": receiver_id,
+            "amount": U128::from(amount),
+            "memo": Option::<String>::None,
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
 pub async fn ft_transfer_call(
     contract: &Contract,
     sender: &Account,
@@ -208,8 +1950,34 @@ pub async fn ft_transfer_call(
         .await
 }
 
+/// Sandbox with the locker and a whitelisted mock FT, and alice registered on both and holding 100 tokens.
+pub async fn setup() -> Result<(Worker<Sandbox>, Account, Account, Contract, Contract, String)> {
+    let worker = near_workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let alice = root.create_subaccount("alice").initial_balance(NearToken::from_near(50)).transact().await?.unwrap();
+
+    let token_locker_contract = deploy_token_locker(&root).await?;
+    let ft_token_contract = deploy_mock_ft(&root).await?;
+    let ft_token_id = ft_token_contract.id().to_string();
+
+    check!(extend_token_white_list(&token_locker_contract, &root, vec![ft_token_contract.id()]));
+    check!(storage_deposit(&token_locker_contract, alice.id()));
+    check!(storage_deposit(&ft_token_contract, alice.id()));
+    check!(storage_deposit(&ft_token_contract, token_locker_contract.id()));
+    check!(mint_ft(&ft_token_contract, alice.id(), NearToken::from_near(100).as_yoctonear()));
+
+    Ok((worker, root, alice, token_locker_contract, ft_token_contract, ft_token_id))
+}
+
 pub async fn deploy_token_locker(
     root: &Account,
+) -> Result<Contract> {
+    deploy_token_locker_from(root, TOKEN_LOCKER_WASM).await
+}
+
+pub async fn deploy_token_locker_from(
+    root: &Account,
+    wasm: &str,
 ) -> Result<Contract> {
     let token_locker = root
         .create_subaccount("token_locker")
@@ -218,7 +1986,7 @@ pub async fn deploy_token_locker(
         .await?
         .unwrap();
     let token_locker = token_locker
-        .deploy(&std::fs::read(TOKEN_LOCKER_WASM).unwrap())
+        .deploy(&std::fs::read(wasm).unwrap())
         .await?
         .unwrap();
     assert!(token_locker
@@ -302,6 +2070,36 @@ pub async fn storage_deposit(
         .await
 }
 
+pub async fn storage_withdraw(
+    contract: &Contract,
+    account: &Account,
+    amount: Option<NearToken>,
+) -> Result<ExecutionFinalResult> {
+    account
+        .call(contract.id(), "storage_withdraw")
+        .args_json(json!({
+            "amount": amount
+        }))
+        .max_gas()
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await
+}
+
+pub async fn storage_balance_of(
+    contract: &Contract,
+    account_id: &AccountId,
+) -> Result<Option<StorageBalance>> {
+    contract
+        .call("storage_balance_of")
+        .args_json(json!({
+            "account_id": account_id
+        }))
+        .view()
+        .await?
+        .json::<Option<StorageBalance>>()
+}
+
 pub async fn storage_unregister(
     contract: &Contract,
     account: &Account,